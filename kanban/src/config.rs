@@ -15,6 +15,28 @@ pub(crate) struct Config {
     pub endpoint: String,
     pub username: String,
     pub password: String,
+    #[serde(default = "default_tls")]
+    pub tls: bool,
+    /// pem bundle to verify server with, instead of fingerprint pinning.
+    #[serde(default)]
+    pub ca_file: Option<String>,
+    /// pinned server certificate fingerprint.
+    #[serde(default)]
+    pub fingerprint: Option<String>,
+}
+
+/// off like the server, which links in plain text until given a cert.
+fn default_tls() -> bool {
+    false
+}
+
+fn optional(input: &str) -> Option<String> {
+    let input = input.trim();
+    if input.is_empty() {
+        None
+    } else {
+        Some(input.to_owned())
+    }
 }
 
 fn default_password() -> String {
@@ -30,6 +52,9 @@ impl Default for Config {
             endpoint: "[::1]:1120".to_owned(),
             username: "名無し".to_owned(),
             password: default_password(),
+            tls: default_tls(),
+            ca_file: None,
+            fingerprint: None,
         }
     }
 }
@@ -62,6 +87,9 @@ impl Config {
             Input::new("endpoint", &self.endpoint, false),
             Input::new("username", &self.username, false),
             Input::new("password", &self.password, false),
+            Input::new("tls", &self.tls.to_string(), false),
+            Input::new("ca_file", self.ca_file.as_deref().unwrap_or_default(), false),
+            Input::new("fingerprint", self.fingerprint.as_deref().unwrap_or_default(), false),
         ]
     }
 
@@ -77,6 +105,15 @@ impl Config {
                 "password" => {
                     self.password = input.to_owned();
                 }
+                "tls" => {
+                    self.tls = input.trim().parse().unwrap_or(self.tls);
+                }
+                "ca_file" => {
+                    self.ca_file = optional(input);
+                }
+                "fingerprint" => {
+                    self.fingerprint = optional(input);
+                }
                 _ => unreachable!(),
             }
         }
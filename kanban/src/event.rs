@@ -22,7 +22,7 @@ pub(crate) enum Mode {
 
 static INPUT_ENABLE: AtomicBool = AtomicBool::new(true);
 
-fn set_mode(on: Mode, input_enable: bool) {
+pub(crate) fn set_mode(on: Mode, input_enable: bool) {
     MODE.store(on);
     INPUT_ENABLE.store(input_enable, Ordering::Release);
 }
@@ -52,16 +52,17 @@ fn handle_panel(s: &Sender<Update>, c: Event) -> anyhow::Result<()> {
         Event::Key(Key::Char('k') | Key::Up) => {
             s.send(Update::Move(Move::Prev))?;
         }
-        Event::Key(Key::Char('i' | 'o')) => {
-            if INPUT_ENABLE.load(Ordering::Acquire) {
-                s.send(Update::Edit(true))?;
-                set_mode(Mode::Input, true);
-            }
+        Event::Key(Key::Char('i' | 'o')) if INPUT_ENABLE.load(Ordering::Acquire) => {
+            s.send(Update::Edit(true))?;
+            set_mode(Mode::Input, true);
         }
         Event::Key(Key::Char('q' | 'n') | Key::Esc) => {
             s.send(Update::PanelAction(PanelAction::Cancel))?;
             set_mode(Mode::Normal, true);
         }
+        Event::Key(Key::Char(' ')) => {
+            s.send(Update::Toggle)?;
+        }
         Event::Key(Key::Char('s' | 'y')) => {
             s.send(Update::PanelAction(PanelAction::Confirm))?;
             set_mode(Mode::Normal, true);
@@ -106,6 +107,12 @@ pub(crate) fn handle(s: Sender<Update>) -> anyhow::Result<()> {
             Event::Key(Key::Char('G')) => {
                 s.send(Update::Move(Move::Bottom))?;
             }
+            Event::Key(Key::Char('A')) => {
+                s.send(Update::Move(Move::Archive))?;
+            }
+            Event::Key(Key::Char('H')) => {
+                s.send(Update::Move(Move::Held))?;
+            }
             Event::Key(Key::Char('s')) => {
                 s.send(Update::OpenPanel(OpenPanel::Setting))?;
                 set_mode(Mode::Panel, true);
@@ -126,6 +133,13 @@ pub(crate) fn handle(s: Sender<Update>) -> anyhow::Result<()> {
                 s.send(Update::OpenPanel(OpenPanel::Delete))?;
                 set_mode(Mode::Panel, false);
             }
+            Event::Key(Key::Char('M')) => {
+                s.send(Update::OpenPanel(OpenPanel::Moderate))?;
+                set_mode(Mode::Panel, true);
+            }
+            Event::Key(Key::Char('e')) => {
+                s.send(Update::OpenPanel(OpenPanel::History))?;
+            }
             Event::Key(Key::Char('U')) => {
                 s.send(Update::OpenPanel(OpenPanel::EditPanel(EditPanel::Update)))?;
                 set_mode(Mode::Panel, true);
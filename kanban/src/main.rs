@@ -12,11 +12,13 @@ use std::{
 mod config;
 mod event;
 mod message;
+mod stamp;
 mod store;
+mod tls;
 mod ui;
 
 fn set_resize_info(s: Sender<Update>) -> anyhow::Result<()> {
-    let mut hook = signal_hook::iterator::Signals::new(&[libc::SIGWINCH])?;
+    let mut hook = signal_hook::iterator::Signals::new([libc::SIGWINCH])?;
     thread::spawn(move || {
         for _ in hook.forever() {
             s.send(Update::Resize).ok();
@@ -52,6 +54,7 @@ fn main() {
         }
     });
     let s_resize = s_back.clone();
+    let s_progress = s_back.clone();
     set_resize_info(s_resize).ok();
     let msg_config = config.clone();
     let message_th = thread::spawn(move || {
@@ -59,7 +62,7 @@ fn main() {
             log::error!("backend message failed: {}", e);
         }
     });
-    if let Err(e) = ui::run(s_main, r_main, config.clone()) {
+    if let Err(e) = ui::run(s_main, r_main, s_progress, config.clone()) {
         log::error!("tui failed: {}", e);
     }
     config.lock().unwrap().save_to_file().ok();
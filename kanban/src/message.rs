@@ -1,25 +1,52 @@
 use crossbeam_channel::{Receiver, Sender};
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::HashMap,
+    fmt,
+    io::ErrorKind,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 use terminus_types::{
-    action::{Action, ListTarget, Response},
-    Error, Node, NodeId,
+    action::{capability, Action, Cursor, Envelope, Hello, ListTarget, Moderation, RequestId, Response},
+    codec::{FrameError, FrameReader, FrameWriter, DEFAULT_MAX_FRAME},
+    stamp::Difficulty,
+    Author, Draft, Error, Limits, Node, NodeId, Patch, Revision, ThreadStats,
 };
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::{tcp::OwnedReadHalf, TcpStream},
+    io::{AsyncRead, AsyncWrite, ReadHalf, WriteHalf},
+    net::TcpStream,
     runtime::Runtime,
 };
 
-use crate::config::Config;
+use crate::{
+    config::Config,
+    tls::{self, UntrustedCert},
+};
 
 #[derive(Debug)]
 pub(crate) enum Request {
     Relink,
     ListRoot,
-    Post(Node),
-    Update(Node),
+    ListArchive,
+    Post(Draft),
+    /// as post, but does not bump the thread.
+    Sage(Draft),
+    Update(Patch),
     Delete(Node),
     List(NodeId),
+    // next page of a list
+    More(ListTarget, Cursor),
+    // push changes under node
+    Subscribe(NodeId),
+    Unsubscribe(NodeId),
+    // moderators only
+    Moderate {
+        author: Author,
+        action: Moderation,
+        reason: String,
+    },
+    // what node said before edits
+    History(NodeId),
     // graceful exit,
     Shutdown,
 }
@@ -39,11 +66,34 @@ impl Request {
 impl From<Request> for Action {
     fn from(req: Request) -> Self {
         match req {
-            Request::ListRoot => Self::List(ListTarget::Root),
-            Request::List(id) => Self::List(ListTarget::Node(id)),
-            Request::Post(node) => Self::Post(node),
-            Request::Update(node) => Self::Update(node),
+            Request::ListRoot => Self::List {
+                target: ListTarget::Root,
+                limit: PAGE_SIZE,
+                cursor: None,
+            },
+            Request::ListArchive => Self::List {
+                target: ListTarget::Archive,
+                limit: PAGE_SIZE,
+                cursor: None,
+            },
+            Request::List(id) => Self::List {
+                target: ListTarget::Node(id),
+                limit: PAGE_SIZE,
+                cursor: None,
+            },
+            Request::More(target, cursor) => Self::List {
+                target,
+                limit: PAGE_SIZE,
+                cursor: Some(cursor),
+            },
+            Request::Post(draft) => Self::Post(draft),
+            Request::Sage(draft) => Self::Sage(draft),
+            Request::Update(patch) => Self::Update(patch),
             Request::Delete(node) => Self::Delete(node),
+            Request::Subscribe(id) => Self::Subscribe(ListTarget::Node(id)),
+            Request::Unsubscribe(id) => Self::Unsubscribe(ListTarget::Node(id)),
+            Request::Moderate { author, action, reason } => Self::Moderate { author, action, reason },
+            Request::History(id) => Self::History(id),
             _ => unreachable!(),
         }
     }
@@ -54,6 +104,64 @@ impl Request {
     pub(crate) fn is_shutdown(&self) -> bool {
         matches!(self, Self::Shutdown)
     }
+
+    fn operation(&self) -> Operation {
+        match self {
+            Request::ListRoot | Request::ListArchive | Request::List(_) | Request::More(..) => Operation::List,
+            Request::Post(_) | Request::Sage(_) => Operation::Post,
+            Request::Update(_) => Operation::Update,
+            Request::Delete(_) => Operation::Delete,
+            Request::Subscribe(_) | Request::Unsubscribe(_) => Operation::Subscribe,
+            Request::Moderate {
+                action: Moderation::Check,
+                ..
+            } => Operation::Check,
+            Request::Moderate {
+                action: Moderation::Held,
+                ..
+            } => Operation::List,
+            Request::Moderate { .. } => Operation::Moderate,
+            Request::History(_) => Operation::History,
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// kind of a request waiting for reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Operation {
+    List,
+    Post,
+    Update,
+    Delete,
+    Subscribe,
+    Moderate,
+    // if we are a moderator
+    Check,
+    History,
+}
+
+impl Operation {
+    /// Returns `true` if success is not worth telling.
+    fn is_quiet(&self) -> bool {
+        matches!(self, Self::List | Self::Subscribe | Self::Check | Self::History)
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Operation::List => "list",
+            Operation::Post => "post",
+            Operation::Update => "update",
+            Operation::Delete => "delete",
+            Operation::Subscribe => "subscribe",
+            Operation::Moderate => "moderate",
+            Operation::Check => "check",
+            Operation::History => "history",
+        };
+        f.write_str(name)
+    }
 }
 
 #[derive(Debug)]
@@ -68,6 +176,8 @@ pub(crate) enum OpenPanel {
     Help,
     Delete,
     Setting,
+    Moderate,
+    History,
     EditPanel(EditPanel),
 }
 
@@ -79,6 +189,10 @@ pub(crate) enum Move {
     Prev,
     Child,
     Parent,
+    // threads off root
+    Archive,
+    // posts and edits held by filter, for moderators
+    Held,
 }
 
 #[derive(Debug)]
@@ -91,109 +205,361 @@ pub(crate) enum PanelAction {
 pub(crate) enum Update {
     Quit,
     Err(Error),
+    // request result
+    Done(Operation),
+    Failed(Operation, Error),
+    TimedOut(Operation),
     // input mode
     Edit(bool),
     Input(char),
     DeleteChar,
+    // flip a yes or no input
+    Toggle,
     // navi
     Move(Move),
     // remote update
     Nodes(Vec<Node>),
+    // threads in archive
+    Archived(Vec<Node>),
+    // cursor of next page, none if no more
+    NextPage(ListTarget, Option<Cursor>),
     DeleteNode(Node),
+    // id the server gave to our post
+    Posted(NodeId),
     // panel action
     OpenPanel(OpenPanel),
     PanelAction(PanelAction),
     // resize notification
     Resize,
+    // redraw for a count down
+    Tick,
+    // ask user to trust server certificate
+    UntrustedCert(UntrustedCert),
+    // handshake done
+    Linked(Hello),
+    // configured identity can moderate
+    Moderator(bool),
+    // board wide line, none to clear
+    Announcement(Option<String>),
+    // node limits of server
+    Limits(Limits),
+    // proof of work posts need, and salt to make it with
+    Difficulty(Difficulty),
+    // tries so far and tries expected, none when stamped
+    Stamping(Option<(u64, u64)>),
+    // revisions of node before its edits
+    History(NodeId, Vec<Revision>),
+    // replies and posters of threads
+    Stats(Vec<(NodeId, ThreadStats)>),
+    // posts and edits waiting for a moderator, with why
+    Held(Vec<(Node, String)>),
 }
 
 impl Update {
-    /// Returns `true` if the update is [`Self::Resize`].
-    pub(crate) fn is_resize(&self) -> bool {
-        matches!(self, Self::Resize)
+    /// Returns `true` if the update only asks to re-draw.
+    pub(crate) fn is_redraw(&self) -> bool {
+        matches!(self, Self::Resize | Self::Tick)
+    }
+
+    /// Returns `true` if the update comes from server, or from work done for it.
+    pub(crate) fn is_remote(&self) -> bool {
+        matches!(
+            self,
+            Self::Err(_)
+                | Self::Done(_)
+                | Self::Failed(..)
+                | Self::TimedOut(_)
+                | Self::Nodes(_)
+                | Self::Archived(_)
+                | Self::NextPage(..)
+                | Self::DeleteNode(_)
+                | Self::Posted(_)
+                | Self::Linked(_)
+                | Self::Moderator(_)
+                | Self::Announcement(_)
+                | Self::Limits(_)
+                | Self::Difficulty(_)
+                | Self::Stamping(_)
+                | Self::History(..)
+                | Self::Stats(_)
+                | Self::Held(_)
+        )
     }
 }
 
+pub(crate) trait Link: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Link for T {}
+
 /// link start!
-/// may upgrade to tls in future.
-async fn link_start(endpoint: &str) -> anyhow::Result<TcpStream> {
-    log::info!("try link to {}", endpoint);
-    Ok(TcpStream::connect(endpoint).await?)
+/// use tls unless disabled in config.
+async fn link_start(config: &Config) -> anyhow::Result<Box<dyn Link>> {
+    log::info!("try link to {}", config.endpoint);
+    let stream = TcpStream::connect(&config.endpoint).await?;
+    if !config.tls {
+        log::warn!("tls disabled, link in plain text.");
+        return Ok(Box::new(stream));
+    }
+    let (connector, seen) = tls::connector(config)?;
+    let server_name = tls::server_name(&config.endpoint);
+    match connector.connect(server_name.as_ref(), stream).await {
+        Ok(stream) => Ok(Box::new(stream)),
+        Err(e) => {
+            if let Some(fingerprint) = seen.lock().unwrap().take() {
+                let changed = config.fingerprint.is_some();
+                return Err(UntrustedCert { fingerprint, changed }.into());
+            }
+            Err(anyhow::Error::from(e).context("tls handshake failed, server may not have tls enabled"))
+        }
+    }
+}
+
+type Reader = FrameReader<ReadHalf<Box<dyn Link>>>;
+type Writer = FrameWriter<WriteHalf<Box<dyn Link>>>;
+type Pending = Arc<Mutex<HashMap<RequestId, (Operation, Instant)>>>;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const PAGE_SIZE: u32 = 50;
+
+/// turn remote response into local update.
+fn apply(s: &Sender<Update>, response: Response) {
+    let update = match response {
+        Response::Err(e) => Update::Err(e),
+        Response::List {
+            target: ListTarget::Archive,
+            nodes,
+            next,
+        } => {
+            s.send(Update::Archived(nodes))
+                .expect("sender droped which should not drop here.");
+            Update::NextPage(ListTarget::Archive, next)
+        }
+        Response::List { target, nodes, next } => {
+            s.send(Update::Nodes(nodes))
+                .expect("sender droped which should not drop here.");
+            Update::NextPage(target, next)
+        }
+        // a tombstone takes the place
+        Response::Delete(node) if node.deleted => Update::Nodes(vec![node]),
+        Response::Delete(node) => Update::DeleteNode(node),
+        Response::Post(node) => {
+            let id = node.id.clone();
+            s.send(Update::Nodes(vec![node]))
+                .expect("sender droped which should not drop here.");
+            Update::Posted(id)
+        }
+        Response::Update(node) => Update::Nodes(vec![node]),
+        Response::Announcement(announcement) => Update::Announcement(announcement),
+        Response::Limits(limits) => Update::Limits(limits),
+        Response::Difficulty(difficulty) => Update::Difficulty(difficulty),
+        Response::History { id, revisions } => Update::History(id, revisions),
+        Response::Stats(stats) => Update::Stats(stats),
+        Response::Held(held) => Update::Held(held),
+        Response::Subscribe(_) | Response::Unsubscribe(_) | Response::Moderate(_) => return,
+    };
+    s.send(update).expect("sender droped which should not drop here.");
+}
+
+/// redraw every second until wait is over.
+async fn count_down(s: Sender<Update>, seconds: u64) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    for _ in 0..=seconds {
+        interval.tick().await;
+        if s.send(Update::Tick).is_err() {
+            return;
+        }
+    }
 }
 
 /// receive from remote.
 /// can deal with subscription.
-async fn receive(s: Sender<Update>, mut read: OwnedReadHalf) -> anyhow::Result<()> {
-    let mut indicator = [0u8; 4];
-    let mut buf = Vec::new();
+async fn receive(s: Sender<Update>, mut read: Reader, pending: Pending) -> anyhow::Result<()> {
     loop {
-        read.read_exact(&mut indicator).await?;
-        let size: u32 = bincode::deserialize(&indicator)?;
-        if size == 0 {
-            log::info!("end signal received.");
-            return Ok(());
-        }
-        buf.resize(size as usize, 0u8);
-        read.read_exact(&mut buf).await?;
-        let update: Response = bincode::deserialize(&buf)?;
-        match update {
-            Response::Err(e) => {
-                s.send(Update::Err(e))
-                    .expect("sender droped which should not drop here.");
+        let frame: Envelope = match read.read().await {
+            Ok(Some(frame)) => frame,
+            Ok(None) => {
+                log::info!("end signal received.");
+                return Ok(());
+            }
+            Err(e) if e.is_recoverable() => {
+                log::warn!("frame skipped: {}.", e);
+                continue;
             }
-            Response::List(list) => {
-                s.send(Update::Nodes(list))
-                    .expect("sender droped which should not drop here.");
+            Err(e) => return Err(e.into()),
+        };
+        match frame {
+            Envelope::Reply { id, response } => {
+                let operation = pending.lock().unwrap().remove(&id).map(|(operation, _)| operation);
+                match (operation, response) {
+                    (Some(Operation::Check), response) => {
+                        let moderator = matches!(response, Response::Moderate(_));
+                        s.send(Update::Moderator(moderator))
+                            .expect("sender droped which should not drop here.");
+                    }
+                    (Some(operation), Response::Err(e)) => {
+                        log::warn!("operation {} failed: {}.", operation, e);
+                        if let Error::RateLimited { retry_after } = e {
+                            tokio::spawn(count_down(s.clone(), retry_after));
+                        }
+                        s.send(Update::Failed(operation, e))
+                            .expect("sender droped which should not drop here.");
+                    }
+                    (Some(operation), response) => {
+                        log::info!("operation {} success.", operation);
+                        apply(&s, response);
+                        if !operation.is_quiet() {
+                            s.send(Update::Done(operation))
+                                .expect("sender droped which should not drop here.");
+                        }
+                    }
+                    (None, response) => {
+                        log::warn!("reply to unknown request {}.", id);
+                        apply(&s, response);
+                    }
+                }
             }
-            Response::Delete(node) => {
-                log::info!("operation delete success.");
-                s.send(Update::DeleteNode(node))
-                    .expect("sender droped which should not drop here.");
+            Envelope::Event(response) => apply(&s, response),
+            Envelope::Request { .. } => {
+                log::warn!("unexpected request from server.");
             }
-            Response::Post(node) | Response::Update(node) => {
-                log::info!("operation post/update success.");
-                s.send(Update::Nodes(vec![node]))
-                    .expect("sender droped which should not drop here.");
-                // do nothing
+        }
+    }
+}
+
+/// drop requests waiting too long.
+async fn expire(s: Sender<Update>, pending: Pending) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        let mut expired = Vec::new();
+        pending.lock().unwrap().retain(|_id, (operation, since)| {
+            if since.elapsed() > REQUEST_TIMEOUT {
+                expired.push(*operation);
+                return false;
             }
+            true
+        });
+        for operation in expired {
+            log::warn!("operation {} timed out.", operation);
+            s.send(Update::TimedOut(operation))
+                .expect("sender droped which should not drop here.");
         }
     }
 }
 
-// u32 0
-const EOS: &[u8] = &[0; 4];
+const CLIENT_NAME: &str = concat!("kanban ", env!("CARGO_PKG_VERSION"));
+const CAPABILITIES: &[&str] = &[capability::NOTIFY, capability::ENVELOPE, capability::SUBSCRIBE];
 
-async fn send(s: Sender<Update>, r: Receiver<Request>, endpoint: &str) -> anyhow::Result<bool> {
+/// exchange hello with server, and check if we can talk.
+async fn handshake(read: &mut Reader, write: &mut Writer) -> anyhow::Result<Hello> {
+    write.write(&Hello::new(CLIENT_NAME, CAPABILITIES)).await?;
+    let frame = match read.next_frame().await {
+        Ok(Some(frame)) => frame,
+        // server without hello drop the link.
+        Err(FrameError::Io(e)) if e.kind() == ErrorKind::UnexpectedEof => return Err(Error::ServerTooOld.into()),
+        Ok(None) => return Err(Error::ServerTooOld.into()),
+        Err(e) => return Err(e.into()),
+    };
+    if !Hello::is_hello(frame) {
+        return Err(Error::ServerTooOld.into());
+    }
+    let hello: Hello = bincode::deserialize(frame)?;
+    hello.check_server()?;
+    if !hello.supports(capability::ENVELOPE) || !hello.supports(capability::SUBSCRIBE) {
+        return Err(Error::ServerTooOld.into());
+    }
+    log::info!("linked to {} v{}.", hello.name, hello.version);
+    Ok(hello)
+}
+
+/// subscriptions: nodes subscribed, kept between links.
+async fn send(
+    s: Sender<Update>,
+    r: Receiver<Request>,
+    config: &Config,
+    subscriptions: &mut Vec<NodeId>,
+) -> anyhow::Result<bool> {
     let mut relink = false;
-    let (read, mut write) = link_start(endpoint).await?.into_split();
-    let recv_task = tokio::spawn(receive(s, read));
+    let (read, write) = tokio::io::split(link_start(config).await?);
+    let mut read = FrameReader::new(read, DEFAULT_MAX_FRAME);
+    let mut write = FrameWriter::new(write, DEFAULT_MAX_FRAME);
+    let hello = match handshake(&mut read, &mut write).await {
+        Err(e) if !config.tls => return Err(e.context("no hello in plain text, server may require tls")),
+        hello => hello?,
+    };
+    s.send(Update::Linked(hello))?;
+    let pending = Pending::default();
+    let recv_task = tokio::spawn(receive(s.clone(), read, pending.clone()));
+    let expire_task = tokio::spawn(expire(s.clone(), pending.clone()));
+    let mut id: RequestId = 0;
+    // root is always subscribed
+    let resubscribe = subscriptions.iter().cloned().map(ListTarget::Node);
+    for target in std::iter::once(ListTarget::Root).chain(resubscribe) {
+        id += 1;
+        pending
+            .lock()
+            .unwrap()
+            .insert(id, (Operation::Subscribe, Instant::now()));
+        let action = Action::Subscribe(target);
+        write.write(&Envelope::Request { id, action }).await?;
+    }
+    let check = Request::Moderate {
+        author: config.gen_author(),
+        action: Moderation::Check,
+        reason: String::new(),
+    };
+    id += 1;
+    pending.lock().unwrap().insert(id, (check.operation(), Instant::now()));
+    let action = check.into();
+    write.write(&Envelope::Request { id, action }).await?;
     while let Ok(req) = r.recv() {
         if req.is_shutdown() || req.is_relink() {
             relink = req.is_relink();
             break;
         }
+        match req {
+            Request::Subscribe(ref node_id) => subscriptions.push(node_id.clone()),
+            Request::Unsubscribe(ref node_id) => subscriptions.retain(|id| id != node_id),
+            _ => {}
+        }
+        id += 1;
+        let operation = req.operation();
+        pending.lock().unwrap().insert(id, (operation, Instant::now()));
         let action: Action = req.into();
-        let size: u32 = bincode::serialized_size(&action)? as u32;
-        let size = bincode::serialize(&size)?;
-        let bin = bincode::serialize(&action)?;
-        write.write_all(&size).await?;
-        write.write_all(&bin).await?;
+        match write.write(&Envelope::Request { id, action }).await {
+            Ok(()) => {}
+            Err(FrameError::Io(e)) => return Err(e.into()),
+            // nothing sent, link still fine
+            Err(e) => {
+                log::warn!("operation {} not sent: {}.", operation, e);
+                pending.lock().unwrap().remove(&id);
+                s.send(Update::Failed(operation, Error::FrameTooLarge))?;
+            }
+        }
     }
-    write.write(EOS).await?;
+    write.finish().await?;
     recv_task.abort();
+    expire_task.abort();
     Ok(relink)
 }
 
 pub(crate) fn handle(s: Sender<Update>, r: Receiver<Request>, config: Arc<Mutex<Config>>) -> anyhow::Result<()> {
     let async_rt = Runtime::new().expect("runtime start up failed");
+    let mut subscriptions = Vec::new();
     loop {
-        let endpoint = config.lock().unwrap().endpoint.clone();
-        match async_rt.block_on(send(s.clone(), r.clone(), &endpoint)) {
-            Err(e) => {
-                log::error!("link failed: {}", e);
-                let error = Update::Err(Error::NetworkError);
-                s.send(error).unwrap();
-            }
+        let link_config = config.lock().unwrap().clone();
+        match async_rt.block_on(send(s.clone(), r.clone(), &link_config, &mut subscriptions)) {
+            Err(e) => match e.downcast::<UntrustedCert>() {
+                Ok(untrusted) => {
+                    log::warn!("link failed: {}", untrusted);
+                    s.send(Update::UntrustedCert(untrusted)).unwrap();
+                }
+                Err(e) => {
+                    log::error!("link failed: {:#}", e);
+                    let error = e.downcast::<Error>().unwrap_or(Error::NetworkError);
+                    s.send(Update::Err(error)).unwrap();
+                }
+            },
             Ok(false) => return Ok(()),
             Ok(true) => continue,
         }
@@ -0,0 +1,60 @@
+//! proof of work for posts, done off the ui thread.
+
+use crate::message::{Request, Update};
+use crossbeam_channel::Sender;
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+use terminus_types::{
+    stamp::{self, Difficulty},
+    Draft,
+};
+
+/// tries between looks at the clock.
+const CHUNK: u64 = 1 << 14;
+/// least time between progress updates.
+const REPORT: Duration = Duration::from_millis(200);
+
+/// stamp draft in background and post it, progress goes to ui.
+/// posted at once if no work is needed, sage replies do not bump.
+pub(crate) fn post(mut draft: Draft, sage: bool, difficulty: Difficulty, s: Sender<Request>, progress: Sender<Update>) {
+    let request = move |draft| {
+        if sage {
+            Request::Sage(draft)
+        } else {
+            Request::Post(draft)
+        }
+    };
+    let Difficulty { bits, salt } = difficulty;
+    if bits == 0 {
+        request(draft).send(&s).ok();
+        return;
+    }
+    thread::spawn(move || {
+        let challenge = draft.challenge(&salt);
+        let expected = stamp::expected_tries(bits);
+        // a different start each time, same posts get new stamps
+        let start: u64 = rand::random();
+        let mut tries = 0;
+        let mut reported = Instant::now();
+        let nonce = loop {
+            if let Some(nonce) = stamp::solve(&challenge, bits, start.wrapping_add(tries), CHUNK) {
+                break nonce;
+            }
+            tries += CHUNK;
+            if reported.elapsed() < REPORT {
+                continue;
+            }
+            reported = Instant::now();
+            // ui is gone
+            if progress.send(Update::Stamping(Some((tries, expected)))).is_err() {
+                return;
+            }
+        };
+        log::info!("stamped after about {} tries.", tries);
+        draft.stamp = Some(nonce);
+        progress.send(Update::Stamping(None)).ok();
+        request(draft).send(&s).ok();
+    });
+}
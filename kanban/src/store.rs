@@ -1,12 +1,16 @@
 use anyhow::Result;
 use sled::{Config, Db};
-use terminus_types::Node;
+use terminus_types::{Node, ThreadStats};
 
 pub(crate) struct Store {
     inner: Db,
 }
 
 const ROOT_LIST: &str = "root_list";
+/// threads off root, never in root list.
+const ARCHIVE: &str = "archive";
+/// thread id to what server counted under it.
+const STATS: &str = "stats";
 impl Store {
     pub(crate) fn new() -> Result<Self> {
         let config = Config::new()
@@ -19,18 +23,44 @@ impl Store {
     pub(crate) fn clear(&self) {
         self.inner.clear().ok();
         self.inner.open_tree(ROOT_LIST).unwrap().clear().ok();
+        self.inner.open_tree(ARCHIVE).unwrap().clear().ok();
+        self.inner.open_tree(STATS).unwrap().clear().ok();
     }
 
     /// can post/update
     pub(crate) fn insert(&self, node: Node) -> Result<()> {
         let value = bincode::serialize(&node)?;
         self.inner.insert(&node.id, value.clone())?;
-        if node.is_top_level() {
+        let archive = self.inner.open_tree(ARCHIVE)?;
+        if node.is_top_level() && archive.contains_key(&node.id)? {
+            archive.insert(&node.id, value)?;
+        } else if node.is_top_level() {
             self.inner.open_tree(ROOT_LIST)?.insert(&node.id, value)?;
         }
         Ok(())
     }
 
+    /// threads listed in archive.
+    pub(crate) fn insert_archived(&self, node: Node) -> Result<()> {
+        let value = bincode::serialize(&node)?;
+        self.inner.insert(&node.id, value.clone())?;
+        self.inner.open_tree(ROOT_LIST)?.remove(&node.id)?;
+        self.inner.open_tree(ARCHIVE)?.insert(&node.id, value)?;
+        Ok(())
+    }
+
+    pub(crate) fn insert_stats(&self, id: &[u8], stats: &ThreadStats) -> Result<()> {
+        self.inner.open_tree(STATS)?.insert(id, bincode::serialize(stats)?)?;
+        Ok(())
+    }
+
+    pub(crate) fn stats(&self, id: &[u8]) -> Result<Option<ThreadStats>> {
+        match self.inner.open_tree(STATS)?.get(id)? {
+            Some(stats) => Ok(Some(bincode::deserialize(&stats)?)),
+            None => Ok(None),
+        }
+    }
+
     pub(crate) fn delete(&self, node: &Node) -> Result<()> {
         if node.is_top_level() {
             self.inner.open_tree(ROOT_LIST).unwrap().remove(&node.id)?;
@@ -39,6 +69,16 @@ impl Store {
         Ok(())
     }
 
+    pub(crate) fn list_archive(&self) -> Result<Vec<Node>> {
+        let mut res = Vec::new();
+        for item in self.inner.open_tree(ARCHIVE)?.iter().values() {
+            res.push(bincode::deserialize(&item?)?);
+        }
+        res.sort_unstable_by_key(|node: &Node| node.last_reply);
+        res.reverse();
+        Ok(res)
+    }
+
     pub(crate) fn list_root(&self) -> Result<Vec<Node>> {
         let mut res = Vec::new();
         let list = self.inner.open_tree(ROOT_LIST)?.iter();
@@ -47,11 +87,13 @@ impl Store {
             let item = bincode::deserialize(&val)?;
             res.push(item);
         }
-        res.sort_unstable_by_key(|node: &Node| node.last_reply);
+        // sticky ones on top
+        res.sort_unstable_by_key(|node: &Node| (node.sticky, node.last_reply));
         res.reverse();
         Ok(res)
     }
 
+    /// ids are big endian, so key order is time order.
     pub(crate) fn list(&self, root: &[u8]) -> Result<Vec<Node>> {
         let mut res = Vec::new();
         let list = self.inner.scan_prefix(root).values();
@@ -59,19 +101,6 @@ impl Store {
             let item = bincode::deserialize(&item?)?;
             res.push(item);
         }
-        res.sort_by_cached_key(|node: &Node| {
-            let mut id: Vec<u128> = Vec::new();
-            let mut n = 0;
-            while let Some(slice) = node.id.get(n..n + 16) {
-                if let Ok(layer) = bincode::deserialize(slice) {
-                    id.push(layer);
-                } else {
-                    log::error!("data deserialize failed!");
-                }
-                n += 16;
-            }
-            id
-        });
         Ok(res)
     }
 }
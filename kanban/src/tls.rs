@@ -0,0 +1,107 @@
+use crate::config::Config;
+use std::{
+    fmt,
+    fs::File,
+    io::BufReader,
+    sync::{Arc, Mutex},
+};
+use terminus_types::cert_fingerprint;
+use tokio_rustls::{
+    rustls::{Certificate, ClientConfig, RootCertStore, ServerCertVerified, ServerCertVerifier, TLSError},
+    webpki::{DNSName, DNSNameRef},
+    TlsConnector,
+};
+
+/// the server presented a certificate we do not trust yet.
+#[derive(Debug)]
+pub(crate) struct UntrustedCert {
+    pub fingerprint: String,
+    /// a different fingerprint was pinned before.
+    pub changed: bool,
+}
+
+impl fmt::Display for UntrustedCert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "untrusted certificate {}", self.fingerprint)
+    }
+}
+
+impl std::error::Error for UntrustedCert {}
+
+/// trust a certificate only by its fingerprint.
+/// the presented fingerprint is kept for trust on first use.
+struct PinnedVerifier {
+    pinned: Option<String>,
+    seen: Arc<Mutex<Option<String>>>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        _roots: &RootCertStore,
+        presented_certs: &[Certificate],
+        _dns_name: DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        let leaf = presented_certs.first().ok_or(TLSError::NoCertificatesPresented)?;
+        let fingerprint = cert_fingerprint(&leaf.0);
+        if self.pinned.as_deref() == Some(fingerprint.as_str()) {
+            return Ok(ServerCertVerified::assertion());
+        }
+        *self.seen.lock().unwrap() = Some(fingerprint);
+        Err(TLSError::General("certificate fingerprint not trusted".to_owned()))
+    }
+}
+
+/// host part of endpoint, without port and brackets.
+fn host(endpoint: &str) -> &str {
+    let host = match endpoint.rfind(':') {
+        // bare ipv6 address without port
+        Some(ind) if !endpoint[..ind].contains(':') || endpoint[..ind].ends_with(']') => &endpoint[..ind],
+        Some(_) | None => endpoint,
+    };
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+/// name to verify the certificate against.
+/// ip endpoints have no dns name, which only works with a pinned fingerprint.
+pub(crate) fn server_name(endpoint: &str) -> DNSName {
+    DNSNameRef::try_from_ascii_str(host(endpoint))
+        .unwrap_or_else(|_| DNSNameRef::try_from_ascii_str("terminus.invalid").unwrap())
+        .to_owned()
+}
+
+/// build a connector from config.
+/// with a ca bundle, the server is verified by it, otherwise by the pinned fingerprint.
+/// the returned slot holds the fingerprint of a rejected certificate.
+pub(crate) fn connector(config: &Config) -> anyhow::Result<(TlsConnector, Arc<Mutex<Option<String>>>)> {
+    let mut tls_config = ClientConfig::new();
+    let seen = Arc::new(Mutex::new(None));
+    if let Some(ref ca_file) = config.ca_file {
+        let mut reader = BufReader::new(File::open(ca_file)?);
+        tls_config
+            .root_store
+            .add_pem_file(&mut reader)
+            .map_err(|_| anyhow::anyhow!("bad ca file {}", ca_file))?;
+    } else {
+        let verifier = PinnedVerifier {
+            pinned: config.fingerprint.clone(),
+            seen: seen.clone(),
+        };
+        tls_config.dangerous().set_certificate_verifier(Arc::new(verifier));
+    }
+    Ok((TlsConnector::from(Arc::new(tls_config)), seen))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::host;
+
+    #[test]
+    fn endpoint_host() {
+        assert_eq!("::1", host("[::1]:1120"));
+        assert_eq!("::1", host("::1"));
+        assert_eq!("board.example", host("board.example:1120"));
+        assert_eq!("127.0.0.1", host("127.0.0.1:1120"));
+    }
+}
@@ -1,62 +1,96 @@
-use super::panel::{Panel, PanelMode};
-use crate::{message::Request, ui::panel::Input};
+use super::panel::{Check, Panel, PanelMode};
+use crate::{
+    message::{Request, Update},
+    stamp,
+    ui::panel::Input,
+};
 use crossbeam_channel::Sender;
-use rand::Rng;
-use terminus_types::{Author, Node};
+use terminus_types::{action::Moderation, stamp::Difficulty, Author, Draft, Limits, Node, NodeId, Patch, ID_LEN};
 
-pub(super) fn post_panel(info: Option<&str>) -> Panel {
-    let inputs = vec![
-        Input::new("title", "言って", false),
+/// title and content typed in.
+fn typed(inputs: &[Input]) -> (String, String) {
+    let (mut title, mut content) = (String::new(), String::new());
+    for Input { label, input, .. } in inputs {
+        match label.as_str() {
+            "title" => title = input.to_string(),
+            "content" => content = input.to_string(),
+            "sage" => {}
+            _ => unreachable!(),
+        }
+    }
+    (title, content)
+}
+
+/// node as it would be with what is typed, checked against limits.
+fn check_node(node: Node, limits: &Limits) -> Check {
+    let limits = limits.clone();
+    Box::new(move |inputs| {
+        let (title, content) = typed(inputs);
+        let node = Node {
+            title,
+            content,
+            ..node.clone()
+        };
+        node.validate(&limits).map_err(|e| e.to_string())
+    })
+}
+
+fn node_inputs(title: &str, content: &str, limits: &Limits) -> Vec<Input> {
+    vec![
+        Input::new("title", title, false).limited(limits.title, None),
         // もっと、もっと、もっと、ちゃんと言って
-        Input::new("content", "", true),
-    ];
+        Input::new("content", content, true).limited(limits.content, Some(limits.lines)),
+    ]
+}
+
+/// parent: empty for a thread, replies can be sage.
+pub(super) fn post_panel(info: Option<&str>, parent: &[u8], limits: &Limits) -> Panel {
+    let mut inputs = node_inputs("言って", "", limits);
+    if !parent.is_empty() {
+        inputs.push(Input::toggle("sage", false));
+    }
+    // id is only for depth
+    let mut id = parent.to_owned();
+    id.extend_from_slice(&[0; ID_LEN]);
+    let node = Node { id, ..Node::empty_node() };
     Panel::new(
         inputs,
         info.unwrap_or("press i to input, ESC to quit, Return to confirm."),
         PanelMode::Panel,
     )
+    .with_check(check_node(node, limits))
 }
 
-pub(super) fn update_panel(info: Option<&str>, node: &Node) -> Panel {
-    let inputs = vec![
-        Input::new("title", &node.title, false),
-        // もっと、もっと、もっと、ちゃんと言って
-        Input::new("content", &node.content, true),
-    ];
+pub(super) fn update_panel(info: Option<&str>, node: &Node, limits: &Limits) -> Panel {
+    let inputs = node_inputs(&node.title, &node.content, limits);
     Panel::new(
         inputs,
         info.unwrap_or("press i to input, ESC to quit, s to confirm."),
         PanelMode::Panel,
     )
+    .with_check(check_node(node.clone(), limits))
 }
 
 /// node_id: parent id
-pub(super) fn post_node(s: &Sender<Request>, id: &[u8], inputs: &[Input], author: Author) -> anyhow::Result<()> {
-    let mut rand = rand::thread_rng();
-    let tail: u64 = rand.gen();
-    let mut node = Node::new(id, "title".to_string(), author, "content".to_string(), tail);
-    for Input { label, input, .. } in inputs {
-        match label.as_str() {
-            "title" => node.title = input.to_string(),
-            "content" => node.content = input.to_string(),
-            _ => unreachable!(),
-        }
-    }
-    let req = Request::Post(node);
-    req.send(s)?;
-    Ok(())
+/// stamped in background first if server needs proof of work.
+pub(super) fn post_node(
+    s: &Sender<Request>,
+    id: &[u8],
+    inputs: &[Input],
+    author: Author,
+    difficulty: Difficulty,
+    progress: &Sender<Update>,
+) {
+    let (title, content) = typed(inputs);
+    let draft = Draft::new(id, title, author, content);
+    let sage = inputs.iter().any(|input| input.label == "sage" && input.is_on());
+    stamp::post(draft, sage, difficulty, s.clone(), progress.clone());
 }
 
-pub(super) fn update_node(s: &Sender<Request>, mut node: Node, inputs: &[Input], author: Author) -> anyhow::Result<()> {
-    for Input { label, input, .. } in inputs {
-        match label.as_str() {
-            "title" => node.title = input.to_string(),
-            "content" => node.content = input.to_string(),
-            _ => unreachable!(),
-        }
-    }
-    node.author = author;
-    let req = Request::Update(node);
+/// only changed fields are sent.
+pub(super) fn update_node(s: &Sender<Request>, node: Node, inputs: &[Input], author: Author) -> anyhow::Result<()> {
+    let (title, content) = typed(inputs);
+    let req = Request::Update(Patch::diff(&node, title, content, author));
     req.send(s)?;
     Ok(())
 }
@@ -75,3 +109,66 @@ pub(super) fn delete_node(s: &Sender<Request>, mut node: Node, author: Author) -
     req.send(s)?;
     Ok(())
 }
+
+pub(super) fn moderate_panel() -> Panel {
+    let inputs = vec![Input::new("action", "delete", false), Input::new("reason", "", false)];
+    Panel::new(
+        inputs,
+        "action is delete, restore, lock, unlock, ban, ban-address, stick or unstick. press i to input, ESC to quit, s to confirm.",
+        PanelMode::Panel,
+    )
+}
+
+/// for a node in held queue.
+pub(super) fn held_panel() -> Panel {
+    let inputs = vec![Input::new("action", "approve", false), Input::new("reason", "", false)];
+    Panel::new(
+        inputs,
+        "action is approve or discard. press i to input, ESC to quit, s to confirm.",
+        PanelMode::Panel,
+    )
+}
+
+pub(super) fn moderate_node(s: &Sender<Request>, id: NodeId, inputs: &[Input], author: Author) -> anyhow::Result<()> {
+    let mut action = "";
+    let mut reason = String::new();
+    for Input { label, input, .. } in inputs {
+        match label.as_str() {
+            "action" => action = input.trim(),
+            "reason" => reason = input.to_string(),
+            _ => unreachable!(),
+        }
+    }
+    let action = match action {
+        "delete" => Moderation::Delete(id),
+        "restore" => Moderation::Restore(id),
+        "lock" => Moderation::Lock(id),
+        "unlock" => Moderation::Unlock(id),
+        "ban" => Moderation::BanTripcode(id),
+        "ban-address" => Moderation::BanAddress(id),
+        "stick" => Moderation::Stick(id),
+        "unstick" => Moderation::Unstick(id),
+        "approve" => Moderation::Approve(id),
+        "discard" => Moderation::Discard(id),
+        other => anyhow::bail!("unknown moderation: {}", other),
+    };
+    let req = Request::Moderate { author, action, reason };
+    req.send(s)?;
+    Ok(())
+}
+
+pub(super) fn trust_confirm(fingerprint: &str, changed: bool) -> Panel {
+    let warning = if changed {
+        "WARNING: server certificate has CHANGED since last link!\n\n"
+    } else {
+        ""
+    };
+    Panel::new(
+        vec![],
+        format!(
+            "{}Server presented a certificate with fingerprint\n\n{}\n\nDo you trust it? [Y/n]",
+            warning, fingerprint
+        ),
+        PanelMode::Info,
+    )
+}
@@ -12,11 +12,15 @@ j,k    next/prev item
 h,l    up/down level
 g      go to top
 G      go to bottom
+A      archived threads, h to go back
+H      held posts and edits, for moderators
 
 n,p    new post
 r      reply to this post
 d      delete this post
 U      update this post
+e      edit history of this post
+M      moderate this post, for moderators
 
 s      open setting
 
@@ -24,6 +28,7 @@ s      open setting
 
 j,k    next/prev
 i,e    input
+space  flip a yes/no input, like sage
 s      commit edit
 ESC    complete input / back to List view without save";
 
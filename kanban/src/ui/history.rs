@@ -0,0 +1,96 @@
+//! what a node said before, and what each edit changed.
+
+use super::panel::{Panel, PanelMode};
+use chrono::{DateTime, Local, Utc};
+use terminus_types::{Node, Revision};
+
+fn local_time(time: DateTime<Utc>) -> String {
+    time.with_timezone(&Local)
+        .to_rfc3339_opts(chrono::SecondsFormat::Secs, false)
+}
+
+/// lines of new against old, removed marked with - and added with +.
+fn diff_lines(old: &str, new: &str, text: &mut String) {
+    for line in diff::lines(old, new) {
+        let (mark, line) = match line {
+            diff::Result::Left(line) => ("- ", line),
+            diff::Result::Right(line) => ("+ ", line),
+            diff::Result::Both(line, _) => ("  ", line),
+        };
+        text.push_str(mark);
+        text.push_str(line);
+        text.push('\n');
+    }
+}
+
+/// every revision with what changed from the one before, node as it is now last.
+fn history_text(node: &Node, revisions: Vec<Revision>) -> String {
+    // each revision was written when the one before was replaced
+    let mut written = node.publish_time;
+    let mut versions = Vec::new();
+    for Revision {
+        title,
+        content,
+        replaced,
+    } in revisions
+    {
+        versions.push((title, content, written));
+        written = replaced;
+    }
+    versions.push((node.title.clone(), node.content.clone(), written));
+    let count = versions.len();
+    let mut text = String::new();
+    let mut before: Option<(String, &str)> = None;
+    for (ind, (title, content, written)) in versions.iter().enumerate() {
+        let title = format!("# {}", title);
+        let (old_title, old_content) = match before {
+            Some((ref title, content)) => (title.as_str(), content),
+            // first one against itself, nothing marked
+            None => (title.as_str(), content.as_str()),
+        };
+        let what = if ind == 0 { "posted" } else { "edited" };
+        text.push_str(&format!(
+            "revision {}/{}, {} {}\n\n",
+            ind + 1,
+            count,
+            what,
+            local_time(*written)
+        ));
+        diff_lines(old_title, &title, &mut text);
+        diff_lines(old_content, content, &mut text);
+        text.push('\n');
+        before = Some((title, content));
+    }
+    text
+}
+
+pub(super) fn history_panel(node: &Node, revisions: Vec<Revision>) -> Panel {
+    Panel::new(vec![], history_text(node, revisions), PanelMode::Diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::history_text;
+    use chrono::{Duration, Utc};
+    use terminus_types::{Author, Draft, Revision};
+
+    #[test]
+    fn revisions_diff() {
+        let author = Author::new("a".to_owned(), "b".to_owned());
+        let mut node = Draft::new(&[], "new".to_owned(), author, "one\nthree".to_owned()).into_node(1);
+        let revisions = vec![Revision {
+            title: "old".to_owned(),
+            content: "one\ntwo".to_owned(),
+            replaced: node.publish_time + Duration::minutes(1),
+        }];
+        node.publish_time = Utc::now() - Duration::hours(1);
+        let text = history_text(&node, revisions);
+        let lines: Vec<&str> = text.lines().filter(|line| !line.starts_with("revision")).collect();
+        assert_eq!(
+            vec!["", "  # old", "  one", "  two", "", "", "- # old", "+ # new", "  one", "- two", "+ three", ""],
+            lines
+        );
+        assert!(text.starts_with("revision 1/2, posted"));
+        assert!(text.contains("revision 2/2, edited"));
+    }
+}
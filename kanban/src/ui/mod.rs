@@ -1,6 +1,7 @@
 use crate::{
     config::Config,
-    message::{EditPanel, Move, OpenPanel, PanelAction, Request, Update},
+    event::{set_mode, Mode},
+    message::{EditPanel, Move, OpenPanel, Operation, PanelAction, Request, Update},
     store::Store,
     ui::panel::PanelMode,
 };
@@ -12,8 +13,13 @@ use std::{
     io::stdout,
     mem::swap,
     sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use terminus_types::{
+    action::{Cursor, ListTarget, Moderation},
+    stamp::Difficulty,
+    Author, Error, Limits, Node, NodeId, ThreadStats,
 };
-use terminus_types::{Node, NodeId};
 use termion::{raw::IntoRawMode, screen::AlternateScreen};
 use tui::{
     backend::{Backend, TermionBackend},
@@ -27,6 +33,7 @@ use unicode_width::UnicodeWidthStr;
 
 mod edit_panel;
 mod help;
+mod history;
 pub(crate) mod panel;
 mod split;
 
@@ -36,12 +43,17 @@ enum State {
     // list mode
     Root,
     Node(NodeId),
+    Archive,
+    Held,
     Setting,
     // panel
     Post,
     Reply(Vec<u8>),
     Update(Node),
     Delete(Node),
+    Moderate(NodeId),
+    TrustCert(String),
+    History,
 }
 
 struct App<'a> {
@@ -52,6 +64,24 @@ struct App<'a> {
     panel: Option<Panel>,
     list_state: ListState,
     cur_stack: Vec<ListState>,
+    // cursor of next page in this list
+    next_page: Option<Cursor>,
+    // next page is wanted and not asked yet
+    load_more: bool,
+    // configured identity is a moderator
+    moderator: bool,
+    // board wide line from server
+    announcement: Option<String>,
+    // throttled operation and when it can retry
+    retry_at: Option<(Operation, Instant)>,
+    // node limits told by server
+    limits: Limits,
+    // proof of work posts need, and salt to make it with
+    difficulty: Difficulty,
+    // tries so far and expected of the stamp in work
+    stamping: Option<(u64, u64)>,
+    // posts and edits waiting for a moderator, with why
+    held: Vec<(Node, String)>,
 }
 
 impl Default for App<'_> {
@@ -60,7 +90,24 @@ impl Default for App<'_> {
     }
 }
 
+/// items left below selected before loading next page.
+const PRELOAD: usize = 5;
 const BLANK: &str = "                                                     ";
+
+/// replies and posters of a thread, and who replied last.
+fn stats_text(stats: &ThreadStats) -> String {
+    let count = |count: u64, one: &str, many: &str| format!("{} {}", count, if count == 1 { one } else { many });
+    let mut text = format!(
+        "  {}, {}",
+        count(stats.replies, "reply", "replies"),
+        count(stats.posters, "poster", "posters")
+    );
+    if let Some(ref last) = stats.last_author {
+        text.push_str(&format!(", last {}#{}", last.name, last.encode_pass(6)));
+    }
+    text
+}
+
 impl App<'_> {
     fn new() -> anyhow::Result<Self> {
         Ok(Self {
@@ -71,6 +118,15 @@ impl App<'_> {
             panel: None,
             cur_stack: Vec::new(),
             list_state: ListState::default(),
+            next_page: None,
+            load_more: false,
+            moderator: false,
+            announcement: None,
+            retry_at: None,
+            limits: Limits::default(),
+            difficulty: Difficulty::default(),
+            stamping: None,
+            held: Vec::new(),
         })
     }
 
@@ -104,7 +160,7 @@ impl App<'_> {
         space = std::cmp::min(space, BLANK.len());
         let width = width - space;
         let blank = ["\n", &BLANK[..space]].concat();
-        let split = content.split('\n').map(|str| str.unicode_split(width)).flatten();
+        let split = content.split('\n').flat_map(|str| str.unicode_split(width));
         let mut content = if let Some(max_height) = max_height {
             let mut content = split
                 .take(max_height)
@@ -129,6 +185,9 @@ impl App<'_> {
         node.author.mask();
         let level = node.id.len() / 16;
         let spaces = level.saturating_sub(1) * 2;
+        if node.deleted {
+            return self.draw_tombstone(&node, width, spaces);
+        }
         // title
         let mut text = self.draw_title(node.title, width, spaces);
         // author part
@@ -138,6 +197,10 @@ impl App<'_> {
             Color::LightGreen
         };
         let edited = Span::styled(if node.edited { "edited " } else { "" }, Style::default().fg(edited));
+        let sticky = Span::styled(
+            if node.sticky { "sticky " } else { "" },
+            Style::default().add_modifier(Modifier::BOLD).fg(Color::LightYellow),
+        );
         let id = Span::styled(node.author.encode_pass(6), Style::default().fg(Color::LightBlue));
         let author = Span::styled(node.author.name, Style::default().fg(Color::LightBlue));
         let splt_sym = Span::from("#");
@@ -148,9 +211,12 @@ impl App<'_> {
                 .to_rfc3339_opts(chrono::SecondsFormat::Secs, false),
             Style::default().add_modifier(Modifier::ITALIC),
         );
-        let mut author_line = vec![edited, author, splt_sym, id, at_sym, publish_time];
+        let mut author_line = vec![sticky, edited, author, splt_sym, id, at_sym, publish_time];
+        if let Some(stats) = self.store.stats(&node.id).ok().flatten() {
+            author_line.push(Span::styled(stats_text(&stats), Style::default().fg(Color::DarkGray)));
+        }
         let line_width: usize = author_line.iter().map(|sp| sp.width()).sum();
-        let blank_len = width - line_width;
+        let blank_len = width.saturating_sub(line_width);
         if blank_len > 0 {
             let blank_len = std::cmp::min(blank_len, BLANK.len());
             author_line.insert(0, Span::from(&BLANK[0..blank_len]));
@@ -163,7 +229,29 @@ impl App<'_> {
         };
         text.extend(Self::draw_content(node.content, width, spaces, max_content_height));
         text.extend(Text::from(Spans::from(author_line)));
-        ListItem::new(text).style(Style::default())
+        let style = if node.sticky {
+            Style::default().fg(Color::Yellow)
+        } else {
+            Style::default()
+        };
+        ListItem::new(text).style(style)
+    }
+
+    /// only where it was and when.
+    fn draw_tombstone<'a>(&self, node: &Node, width: usize, spaces: usize) -> ListItem<'a> {
+        let mut text = self.draw_title("[deleted]".to_owned(), width, spaces);
+        let publish_time = node
+            .publish_time
+            .with_timezone(&Local)
+            .to_rfc3339_opts(chrono::SecondsFormat::Secs, false);
+        let publish_time = Span::styled(publish_time, Style::default().add_modifier(Modifier::ITALIC));
+        let mut author_line = vec![publish_time];
+        let blank_len = width.saturating_sub(author_line[0].width());
+        if blank_len > 0 {
+            author_line.insert(0, Span::from(&BLANK[0..blank_len.min(BLANK.len())]));
+        }
+        text.extend(Text::from(Spans::from(author_line)));
+        ListItem::new(text).style(Style::default().fg(Color::DarkGray))
     }
 
     fn draw_list<B: Backend>(&mut self, f: &mut Frame<B>, area: Rect) {
@@ -179,6 +267,33 @@ impl App<'_> {
             .block(main)
             .highlight_style(Style::default().bg(Color::Cyan).fg(Color::White));
         f.render_stateful_widget(list, area, &mut self.list_state);
+        // close to bottom, ask for next page
+        if let Some(selected) = self.list_state.selected() {
+            if selected + PRELOAD >= self.list.len() && self.next_page.is_some() {
+                self.load_more = true;
+            }
+        }
+    }
+
+    /// request for next page, if wanted.
+    fn more(&mut self) -> Option<Request> {
+        if !self.load_more {
+            return None;
+        }
+        self.load_more = false;
+        let target = self.target()?;
+        let cursor = self.next_page.take()?;
+        Some(Request::More(target, cursor))
+    }
+
+    /// list target of current view.
+    fn target(&self) -> Option<ListTarget> {
+        match self.state() {
+            State::Root => Some(ListTarget::Root),
+            State::Node(id) => Some(ListTarget::Node(id.clone())),
+            State::Archive => Some(ListTarget::Archive),
+            _ => None,
+        }
     }
 
     fn default_info<'a>() -> Spans<'a> {
@@ -189,8 +304,39 @@ impl App<'_> {
         ])
     }
 
+    /// tell the wait left of a throttled operation.
+    fn count_down(&mut self) {
+        if let Some((operation, at)) = self.retry_at {
+            let now = Instant::now();
+            if at > now {
+                let left = (at - now).as_secs() + 1;
+                self.set_info_err(format!("{} throttled, retry in {}s.", operation, left));
+            } else {
+                self.retry_at = None;
+                self.set_info(format!("you can {} again.", operation));
+            }
+        }
+    }
+
+    /// tell how far the stamp in work is.
+    fn stamp_progress(&mut self) {
+        if let Some((tries, expected)) = self.stamping {
+            // can take longer than expected, never tell it done
+            let percent = (tries.saturating_mul(100) / expected.max(1)).min(99);
+            self.set_info(format!("stamping post, about {}%...", percent));
+        }
+    }
+
     fn draw_info<B: Backend>(&mut self, f: &mut Frame<B>, area: Rect) {
-        let infomation_block = Block::default().borders(Borders::ALL);
+        self.count_down();
+        self.stamp_progress();
+        let mut infomation_block = Block::default().borders(Borders::ALL);
+        if let Some(ref announcement) = self.announcement {
+            infomation_block = infomation_block.title(Span::styled(
+                announcement.clone(),
+                Style::default().add_modifier(Modifier::BOLD).fg(Color::LightYellow),
+            ));
+        }
         let info = Paragraph::new(self.info.clone())
             .block(infomation_block)
             .wrap(Wrap { trim: true });
@@ -232,6 +378,21 @@ impl App<'_> {
             State::Node(node) => {
                 self.list = self.store.list(node)?;
             }
+            State::Archive => {
+                self.list = self.store.list_archive()?;
+            }
+            State::Held => {
+                // why it is held goes before what it says
+                self.list = self
+                    .held
+                    .iter()
+                    .map(|(node, reason)| {
+                        let mut node = node.clone();
+                        node.content = format!("held: {}\n\n{}", reason, node.content);
+                        node
+                    })
+                    .collect();
+            }
             _ => {
                 return Ok(());
             }
@@ -296,34 +457,187 @@ impl App<'_> {
                 return;
             }
         }
+        // held nodes are not on board yet
+        if let State::Held = self.state() {
+            return;
+        }
         let mut new_list_state = ListState::default();
         new_list_state.select(Some(0));
         swap(&mut self.list_state, &mut new_list_state);
         self.cur_stack.push(new_list_state);
         self.state.push(State::Node(node_id.clone()));
+        self.next_page = None;
+        // replies deeper are covered by thread subscription
+        if node_id.len() == 16 {
+            Request::Subscribe(node_id.clone()).send(s).unwrap();
+        }
         let req = Request::List(node_id);
         req.send(s).unwrap();
     }
 
-    fn go_above(&mut self, s: &Sender<Request>) {
-        let node_id = if let State::Node(ref id) = self.state() {
-            id.to_owned()
-        } else {
+    fn go_archive(&mut self, s: &Sender<Request>) {
+        if !matches!(self.state(), State::Root) {
             return;
+        }
+        let mut new_list_state = ListState::default();
+        new_list_state.select(Some(0));
+        swap(&mut self.list_state, &mut new_list_state);
+        self.cur_stack.push(new_list_state);
+        self.state.push(State::Archive);
+        self.next_page = None;
+        Request::ListArchive.send(s).unwrap();
+    }
+
+    fn go_held(&mut self, s: &Sender<Request>, author: Author) {
+        if !matches!(self.state(), State::Root) {
+            return;
+        }
+        let mut new_list_state = ListState::default();
+        new_list_state.select(Some(0));
+        swap(&mut self.list_state, &mut new_list_state);
+        self.cur_stack.push(new_list_state);
+        self.state.push(State::Held);
+        self.next_page = None;
+        self.held.clear();
+        list_held(s, author);
+    }
+
+    fn go_above(&mut self, s: &Sender<Request>) {
+        let node_id = match self.state() {
+            State::Node(id) => id.to_owned(),
+            State::Archive | State::Held => {
+                self.list_state = self.cur_stack.pop().unwrap_or_default();
+                self.state.pop();
+                self.next_page = None;
+                Request::ListRoot.send(s).unwrap();
+                return;
+            }
+            _ => return,
         };
         // check length
         let length = node_id.len();
         let prev_cur = self.cur_stack.pop().unwrap_or_default();
         self.list_state = prev_cur;
         self.state.pop();
+        self.next_page = None;
         let req = if length <= 16 {
-            Request::ListRoot
+            Request::Unsubscribe(node_id).send(s).unwrap();
+            if let State::Archive = self.state() {
+                Request::ListArchive
+            } else {
+                Request::ListRoot
+            }
         } else {
             Request::List(node_id)
         };
         req.send(s).unwrap();
     }
 
+    /// deal with update from server.
+    fn remote_update(&mut self, update: Update) -> anyhow::Result<()> {
+        match update {
+            Update::Err(e) => {
+                self.set_info_err(e.to_string());
+            }
+            Update::Done(operation) => {
+                self.set_info(format!("{} success.", operation));
+            }
+            Update::Failed(operation, Error::RateLimited { retry_after }) => {
+                self.retry_at = Some((operation, Instant::now() + Duration::from_secs(retry_after)));
+            }
+            Update::Failed(operation, Error::StampInvalid { difficulty }) => {
+                self.difficulty.bits = difficulty;
+                self.set_info_err(format!(
+                    "{} failed: stamp too weak, {} bits needed now, try again.",
+                    operation, difficulty
+                ));
+            }
+            Update::Failed(operation, Error::Held) => {
+                self.set_info(format!("{} held for moderation.", operation));
+            }
+            Update::Failed(operation, e) => {
+                self.set_info_err(format!("{} failed: {}", operation, e));
+            }
+            Update::TimedOut(operation) => {
+                self.set_info_err(format!("{} timed out.", operation));
+            }
+            Update::Nodes(nodes) => {
+                for node in nodes {
+                    self.store.insert(node)?;
+                }
+                self.refesh_list()?;
+            }
+            Update::Archived(nodes) => {
+                for node in nodes {
+                    self.store.insert_archived(node)?;
+                }
+                self.refesh_list()?;
+            }
+            Update::DeleteNode(node) => {
+                self.store.delete(&node).ok();
+                self.refesh_list()?;
+            }
+            Update::Stats(stats) => {
+                for (id, stats) in stats.iter() {
+                    self.store.insert_stats(id, stats)?;
+                }
+                self.refesh_list()?;
+            }
+            Update::Held(held) => {
+                self.held = held;
+                self.refesh_list()?;
+            }
+            Update::Posted(id) => {
+                // jump to our new post if it is in sight
+                if let Some(now) = self.list.iter().position(|node| node.id == id) {
+                    self.list_state.select(Some(now));
+                }
+            }
+            Update::NextPage(target, next) => {
+                if self.target() == Some(target) {
+                    self.next_page = next;
+                }
+            }
+            Update::Linked(hello) => {
+                // told again after check
+                self.moderator = false;
+                self.set_info(format!("linked to {}, press ? for help!", hello.name));
+            }
+            Update::Moderator(moderator) => {
+                self.moderator = moderator;
+            }
+            Update::Announcement(announcement) => {
+                self.announcement = announcement;
+            }
+            Update::Limits(limits) => {
+                self.limits = limits;
+            }
+            Update::Difficulty(difficulty) => {
+                self.difficulty = difficulty;
+            }
+            Update::Stamping(Some(progress)) => {
+                self.stamping = Some(progress);
+            }
+            Update::Stamping(None) => {
+                self.stamping = None;
+                self.set_info("stamped, posting.");
+            }
+            Update::History(id, revisions) => {
+                // node may be gone from sight, or another panel opened meanwhile
+                match self.list.iter().find(|node| node.id == id) {
+                    Some(node) if self.panel.is_none() => {
+                        self.panel = Some(history::history_panel(node, revisions));
+                        self.state.push(State::History);
+                        set_mode(Mode::Panel, false);
+                    }
+                    _ => {}
+                }
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
     /// Get a reference to the app's state.
     fn state(&self) -> &State {
         self.state.last().unwrap()
@@ -338,8 +652,24 @@ impl App<'_> {
     }
 }
 
+/// ask for what waits in held queue.
+fn list_held(s: &Sender<Request>, author: Author) {
+    let req = Request::Moderate {
+        author,
+        action: Moderation::Held,
+        reason: String::new(),
+    };
+    req.send(s).unwrap();
+}
+
 const ROOT_ID: &Vec<u8> = &vec![];
-pub(crate) fn run(s: Sender<Request>, r: Receiver<Update>, config: Arc<Mutex<Config>>) -> anyhow::Result<()> {
+/// progress: where work done for requests tells how it goes.
+pub(crate) fn run(
+    s: Sender<Request>,
+    r: Receiver<Update>,
+    progress: Sender<Update>,
+    config: Arc<Mutex<Config>>,
+) -> anyhow::Result<()> {
     let stdout = stdout().into_raw_mode()?;
     let stdout = AlternateScreen::from(stdout);
     let backend = TermionBackend::new(stdout);
@@ -351,14 +681,22 @@ pub(crate) fn run(s: Sender<Request>, r: Receiver<Update>, config: Arc<Mutex<Con
     app.set_info("press ? for help!");
     loop {
         terminal.draw(|f| app.draw(f))?;
+        if let Some(req) = app.more() {
+            req.send(&s)?;
+        }
         let event = r.recv()?;
-        if event.is_resize() {
+        if event.is_redraw() {
             // just re-draw
             continue;
         }
         if let Some(panel) = app.panel.as_mut() {
             match event {
                 Update::PanelAction(PanelAction::Confirm) => {
+                    if panel.validate().is_err() {
+                        // the panel tells what is wrong, keep editing
+                        set_mode(Mode::Panel, true);
+                        continue;
+                    }
                     let inputs = panel.inputs();
                     match app.state.pop().unwrap() {
                         State::Setting => {
@@ -378,10 +716,12 @@ pub(crate) fn run(s: Sender<Request>, r: Receiver<Update>, config: Arc<Mutex<Con
                             } else {
                                 ROOT_ID
                             };
-                            edit_panel::post_node(&s, node_id, inputs, config.lock().unwrap().gen_author()).unwrap();
+                            let author = config.lock().unwrap().gen_author();
+                            edit_panel::post_node(&s, node_id, inputs, author, app.difficulty, &progress);
                         }
                         State::Reply(ref node_id) => {
-                            edit_panel::post_node(&s, node_id, inputs, config.lock().unwrap().gen_author()).unwrap();
+                            let author = config.lock().unwrap().gen_author();
+                            edit_panel::post_node(&s, node_id, inputs, author, app.difficulty, &progress);
                         }
                         State::Update(node) => {
                             edit_panel::update_node(&s, node, inputs, config.lock().unwrap().gen_author()).unwrap();
@@ -389,14 +729,43 @@ pub(crate) fn run(s: Sender<Request>, r: Receiver<Update>, config: Arc<Mutex<Con
                         State::Delete(node) => {
                             edit_panel::delete_node(&s, node, config.lock().unwrap().gen_author()).unwrap();
                         }
-                        State::Help => {}
+                        State::Moderate(node_id) => {
+                            let author = config.lock().unwrap().gen_author();
+                            if let Err(e) = edit_panel::moderate_node(&s, node_id, inputs, author.clone()) {
+                                app.set_info_err(e.to_string());
+                            }
+                            // what is left in queue after approve or discard
+                            if let State::Held = app.state() {
+                                list_held(&s, author);
+                            }
+                        }
+                        State::TrustCert(fingerprint) => {
+                            let mut config = config.lock().unwrap();
+                            config.fingerprint = Some(fingerprint);
+                            config.save_to_file().ok();
+                            s.send(Request::Relink)?;
+                            let req = Request::ListRoot;
+                            req.send(&s)?;
+                            app.store.clear();
+                            app.refesh_list()?;
+                        }
+                        State::Help | State::History => {}
                         _ => unreachable!(),
                     }
                     app.panel = None;
+                    app.refesh_list()?;
                 }
                 Update::PanelAction(PanelAction::Cancel) => {
                     app.state.pop();
                     app.panel = None;
+                    app.refesh_list()?;
+                }
+                _ if event.is_remote() => {
+                    app.remote_update(event)?;
+                }
+                Update::UntrustedCert(untrusted) => {
+                    // can not prompt over another panel
+                    app.set_info_err(untrusted.to_string());
                 }
                 _ => {
                     panel.handle(event);
@@ -405,18 +774,13 @@ pub(crate) fn run(s: Sender<Request>, r: Receiver<Update>, config: Arc<Mutex<Con
             continue;
         }
         match event {
-            Update::Err(e) => {
-                app.set_info_err(e.to_string());
+            _ if event.is_remote() => {
+                app.remote_update(event)?;
             }
-            Update::Nodes(nodes) => {
-                for node in nodes {
-                    app.store.insert(node)?;
-                }
-                app.refesh_list()?;
-            }
-            Update::DeleteNode(node) => {
-                app.store.delete(&node).ok();
-                app.refesh_list()?;
+            Update::UntrustedCert(untrusted) => {
+                app.panel = Some(edit_panel::trust_confirm(&untrusted.fingerprint, untrusted.changed));
+                app.state.push(State::TrustCert(untrusted.fingerprint));
+                set_mode(Mode::Panel, false);
             }
             Update::Quit => {
                 // press 'q'
@@ -445,6 +809,18 @@ pub(crate) fn run(s: Sender<Request>, r: Receiver<Update>, config: Arc<Mutex<Con
             Update::Move(Move::Bottom) => {
                 app.bottom();
             }
+            Update::Move(Move::Archive) => {
+                app.go_archive(&s);
+                app.refesh_list()?;
+            }
+            Update::Move(Move::Held) => {
+                if app.moderator {
+                    app.go_held(&s, config.lock().unwrap().gen_author());
+                    app.refesh_list()?;
+                } else {
+                    app.set_info_err("not a moderator.".to_owned());
+                }
+            }
             Update::OpenPanel(OpenPanel::Setting) => {
                 let inputs = config.lock().unwrap().gen_inputs();
                 let panel = Panel::new(
@@ -456,13 +832,18 @@ pub(crate) fn run(s: Sender<Request>, r: Receiver<Update>, config: Arc<Mutex<Con
                 app.state.push(State::Setting);
             }
             Update::OpenPanel(OpenPanel::EditPanel(EditPanel::Post)) => {
-                app.panel = Some(edit_panel::post_panel(None));
+                let parent = if let State::Node(node_id) = app.state() {
+                    node_id
+                } else {
+                    ROOT_ID
+                };
+                app.panel = Some(edit_panel::post_panel(None, parent, &app.limits));
                 app.state.push(State::Post);
             }
             Update::OpenPanel(OpenPanel::EditPanel(EditPanel::Update)) => {
                 if let Some(node) = app.selected() {
                     let node = node.clone();
-                    app.panel = Some(edit_panel::update_panel(None, &node));
+                    app.panel = Some(edit_panel::update_panel(None, &node, &app.limits));
                     app.state.push(State::Update(node));
                 }
             }
@@ -472,7 +853,7 @@ pub(crate) fn run(s: Sender<Request>, r: Receiver<Update>, config: Arc<Mutex<Con
                 } else {
                     continue;
                 };
-                app.panel = Some(edit_panel::post_panel(Some("reply to node")));
+                app.panel = Some(edit_panel::post_panel(Some("reply to node"), &node_id, &app.limits));
                 app.state.push(State::Reply(node_id));
             }
             Update::OpenPanel(OpenPanel::Help) => {
@@ -486,6 +867,30 @@ pub(crate) fn run(s: Sender<Request>, r: Receiver<Update>, config: Arc<Mutex<Con
                     app.state.push(State::Delete(node));
                 }
             }
+            Update::OpenPanel(OpenPanel::History) => match app.selected() {
+                Some(node) if node.edited => {
+                    Request::History(node.id.clone()).send(&s)?;
+                    app.set_info("loading history...");
+                }
+                Some(_) => app.set_info("never edited."),
+                None => {}
+            },
+            Update::OpenPanel(OpenPanel::Moderate) => match app.selected() {
+                Some(node) if app.moderator => {
+                    let node_id = node.id.clone();
+                    app.panel = Some(match app.state() {
+                        State::Held => edit_panel::held_panel(),
+                        _ => edit_panel::moderate_panel(),
+                    });
+                    app.state.push(State::Moderate(node_id));
+                }
+                _ => {
+                    set_mode(Mode::Normal, true);
+                    if !app.moderator {
+                        app.set_info_err("not a moderator.".to_owned());
+                    }
+                }
+            },
             _ => unreachable!(),
         }
     }
@@ -16,6 +16,10 @@ pub(crate) struct Input {
     pub label: String,
     pub input: String, // buffer
     pub multi_line: bool,
+    // most chars and lines, counted in title
+    pub limit: Option<(usize, Option<usize>)>,
+    // yes or no, flipped by space instead of typed
+    pub toggle: bool,
 }
 
 impl Input {
@@ -24,9 +28,50 @@ impl Input {
             label: label.as_ref().to_owned(),
             input: input.as_ref().to_owned(),
             multi_line,
+            limit: None,
+            toggle: false,
         }
     }
 
+    pub(crate) fn toggle<T: AsRef<str>>(label: T, on: bool) -> Self {
+        let mut input = Self::new(label.as_ref(), if on { "yes" } else { "no" }, false);
+        input.toggle = true;
+        input
+    }
+
+    /// a toggle switched on.
+    pub(crate) fn is_on(&self) -> bool {
+        self.toggle && self.input == "yes"
+    }
+
+    /// count chars, and lines if given, against limits.
+    pub(crate) fn limited(mut self, chars: usize, lines: Option<usize>) -> Self {
+        self.limit = Some((chars, lines));
+        self
+    }
+
+    fn title(&self) -> Spans<'_> {
+        let (chars, lines) = match self.limit {
+            Some(limit) => limit,
+            None if self.toggle => return Spans::from(format!("{}, space to flip", self.label)),
+            None => return Spans::from(Span::raw(&self.label)),
+        };
+        let count = |now: usize, most: usize| {
+            let style = if now > most {
+                Style::default().fg(Color::LightRed)
+            } else {
+                Style::default()
+            };
+            Span::styled(format!(" {}/{}", now, most), style)
+        };
+        let mut title = vec![Span::raw(&self.label), count(self.input.chars().count(), chars)];
+        if let Some(lines) = lines {
+            title.push(count(self.input.lines().count(), lines));
+            title.push(Span::raw(" lines"));
+        }
+        Spans::from(title)
+    }
+
     fn draw<B: Backend>(&self, f: &mut Frame<B>, area: Rect, selected: bool, edit: bool) {
         let style = Style {
             fg: if selected { Some(Color::LightYellow) } else { None },
@@ -35,10 +80,10 @@ impl Input {
         let block = Block::default()
             .border_style(style)
             .borders(Borders::all())
-            .title(Span::raw(&self.label));
+            .title(self.title());
         let width = block.inner(area).width as usize;
         if self.multi_line {
-            let split = self.input.split('\n').map(|str| str.unicode_split(width)).flatten();
+            let split = self.input.split('\n').flat_map(|str| str.unicode_split(width));
             let count = std::cmp::max(split.clone().count(), 3);
             let mut take: Vec<&str> = split.skip(count - 3).collect();
             if take.last().map(|str| str.width_cjk()) == Some(width) || self.input.ends_with('\n') {
@@ -86,8 +131,13 @@ impl Input {
 pub(super) enum PanelMode {
     Panel,
     Info,
+    /// info with lines marked - and + colored.
+    Diff,
 }
 
+/// tells why inputs can not be sent.
+pub(super) type Check = Box<dyn Fn(&[Input]) -> Result<(), String>>;
+
 pub(super) struct Panel {
     edit: bool,
     info: String,
@@ -95,6 +145,7 @@ pub(super) struct Panel {
     cursor: usize,
     mode: PanelMode,
     inputs: Vec<Input>,
+    check: Option<Check>,
 }
 
 impl Panel {
@@ -107,6 +158,20 @@ impl Panel {
             scroll: 0,
             edit: false,
             info: info.as_ref().to_owned(),
+            check: None,
+        }
+    }
+
+    /// inputs are checked as typed, and can not be confirmed until passed.
+    pub(super) fn with_check(mut self, check: Check) -> Self {
+        self.check = Some(check);
+        self
+    }
+
+    pub(super) fn validate(&self) -> Result<(), String> {
+        match self.check {
+            Some(ref check) => check(&self.inputs),
+            None => Ok(()),
         }
     }
 
@@ -124,7 +189,7 @@ impl Panel {
                         self.cursor = 0;
                     }
                 }
-                PanelMode::Info => {
+                PanelMode::Info | PanelMode::Diff => {
                     let text = Text::from(self.info.as_str());
                     let h = text.height() as u16;
                     if self.scroll < h.saturating_sub(5) {
@@ -140,10 +205,19 @@ impl Panel {
                         self.cursor = self.inputs.len() - 1;
                     }
                 }
-                PanelMode::Info => {
+                PanelMode::Info | PanelMode::Diff => {
                     self.scroll = self.scroll.saturating_sub(1);
                 }
             },
+            // info panels have no inputs
+            Update::Toggle => {
+                if let Some(input) = self.inputs.get_mut(self.cursor).filter(|input| input.toggle) {
+                    let on = input.is_on();
+                    input.input = if on { "no" } else { "yes" }.to_owned();
+                }
+            }
+            // toggles are not typed in
+            Update::Input(_) | Update::DeleteChar if self.inputs[self.cursor].toggle => {}
             Update::Input('\n') => {
                 let input = &mut self.inputs[self.cursor];
                 if !input.input.is_empty() && input.multi_line {
@@ -181,7 +255,7 @@ impl Panel {
         // margin 1, info 3.
         let height = height + 2 + 3;
         // if height is higher than area, means you should use a bigger terminal.
-        let spaces = area.height.checked_sub(height).unwrap_or_default();
+        let spaces = area.height.saturating_sub(height);
         let top = spaces / 2;
         let mut chunks = vec![Constraint::Max(top)];
         for input in self.inputs.iter() {
@@ -205,13 +279,17 @@ impl Panel {
         let mut layout = self.panel_layout(terminal);
         let info = layout.pop().unwrap();
         // should always be same length
-        for (ind, (input, area)) in self.inputs.iter().zip(layout.into_iter()).enumerate() {
+        for (ind, (input, area)) in self.inputs.iter().zip(layout).enumerate() {
             input.draw(f, area, ind == self.cursor, ind == self.cursor && self.edit);
         }
-        // draw Info
-        let text = Paragraph::new(self.info.as_str())
+        // draw Info, or what is wrong
+        let (tell, color) = match self.validate() {
+            Ok(()) => (self.info.clone(), Color::LightBlue),
+            Err(e) => (e, Color::LightRed),
+        };
+        let text = Paragraph::new(tell)
             .wrap(Wrap { trim: true })
-            .style(Style::default().fg(Color::LightBlue))
+            .style(Style::default().fg(color))
             .block(Block::default().borders(Borders::all()));
         f.render_widget(text, info);
     }
@@ -237,15 +315,34 @@ impl Panel {
                 Constraint::Percentage(10),
             ])
             .split(horizontal[1]);
-        let block = Block::default().borders(Borders::all()).title("info");
-        let text = Paragraph::new(self.info.as_str()).block(block).scroll((self.scroll, 0));
+        let (title, text) = match self.mode {
+            PanelMode::Diff => ("history", Self::diff_text(&self.info)),
+            _ => ("info", Text::from(self.info.as_str())),
+        };
+        let block = Block::default().borders(Borders::all()).title(title);
+        let text = Paragraph::new(text).block(block).scroll((self.scroll, 0));
         f.render_widget(text, area[1]);
     }
 
+    fn diff_text(info: &str) -> Text<'_> {
+        let lines: Vec<Spans> = info
+            .lines()
+            .map(|line| {
+                let style = match line.get(..2) {
+                    Some("- ") => Style::default().fg(Color::LightRed),
+                    Some("+ ") => Style::default().fg(Color::LightGreen),
+                    _ => Style::default(),
+                };
+                Spans::from(Span::styled(line, style))
+            })
+            .collect();
+        Text::from(lines)
+    }
+
     pub(super) fn draw<B: Backend>(&self, f: &mut Frame<B>) {
         match self.mode {
             PanelMode::Panel => self.draw_panel(f),
-            PanelMode::Info => self.draw_info(f),
+            PanelMode::Info | PanelMode::Diff => self.draw_info(f),
         }
     }
 
@@ -49,11 +49,11 @@ impl<'a> Iterator for Split<'a> {
 }
 
 pub(crate) trait UnicodeSplit: UnicodeSegmentation {
-    fn unicode_split(&self, len: usize) -> Split;
+    fn unicode_split(&self, len: usize) -> Split<'_>;
 }
 
 impl UnicodeSplit for str {
-    fn unicode_split(&self, len: usize) -> Split {
+    fn unicode_split(&self, len: usize) -> Split<'_> {
         Split::new(self, len)
     }
 }
@@ -0,0 +1,395 @@
+//! server settings, from a toml file and the command line.
+
+use chrono::Duration;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::{
+    fs::{self, OpenOptions},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+use structopt::StructOpt;
+use terminus_types::{codec::DEFAULT_MAX_FRAME, Limits};
+use tokio::sync::watch;
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default)]
+pub(crate) struct Config {
+    /// addresses to listen, v4 or v6.
+    pub listen: Vec<SocketAddr>,
+    pub database: PathBuf,
+    /// board name told in hello.
+    pub name: String,
+    pub log: Log,
+    /// tls is enabled when both are set.
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    /// minutes a node can be deleted after posted.
+    pub delete_window_minutes: i64,
+    /// days a deleted node can be restored by moderators.
+    pub retention_days: i64,
+    /// minutes a node can be edited after posted, no limit if not set.
+    pub edit_window_minutes: Option<i64>,
+    /// biggest frame in bytes, for links made after it is set.
+    pub max_frame: u32,
+    pub limits: Limits,
+    /// keys of moderators, as `terminus-server key` prints.
+    pub moderators: Vec<String>,
+    /// board wide line shown by clients.
+    pub announcement: Option<String>,
+    pub rate_limits: RateLimits,
+    pub proof_of_work: ProofOfWork,
+    /// toml file of content filter rules, reloaded with config.
+    pub filter_rules: Option<PathBuf>,
+    pub threads: Threads,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default)]
+pub(crate) struct Log {
+    /// stdout, stderr or a file path.
+    pub target: String,
+    /// off, error, warn, info, debug or trace.
+    pub level: String,
+}
+
+impl Default for Log {
+    fn default() -> Self {
+        Self {
+            target: "stdout".to_owned(),
+            level: "info".to_owned(),
+        }
+    }
+}
+
+/// a token bucket, posts burst at once then one every interval.
+/// interval of zero is no limit.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub(crate) struct Rate {
+    pub burst: u32,
+    pub interval_seconds: u64,
+}
+
+/// posting limits of each source address and each tripcode.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default)]
+pub(crate) struct RateLimits {
+    pub address_threads: Rate,
+    pub address_replies: Rate,
+    pub tripcode_threads: Rate,
+    pub tripcode_replies: Rate,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        let threads = Rate {
+            burst: 5,
+            interval_seconds: 120,
+        };
+        let replies = Rate {
+            burst: 20,
+            interval_seconds: 10,
+        };
+        Self {
+            address_threads: threads.clone(),
+            address_replies: replies.clone(),
+            tripcode_threads: threads,
+            tripcode_replies: replies,
+        }
+    }
+}
+
+/// stamps posts need, harder when busy.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default)]
+pub(crate) struct ProofOfWork {
+    /// bits needed when quiet, zero turns it off.
+    pub difficulty: u32,
+    /// most bits ever needed.
+    pub max_difficulty: u32,
+    /// posts counted for load are those in the window.
+    pub window_seconds: u64,
+    /// one more bit for every so many posts in the window.
+    pub posts_per_bit: u32,
+}
+
+impl Default for ProofOfWork {
+    fn default() -> Self {
+        Self {
+            difficulty: 0,
+            max_difficulty: 24,
+            window_seconds: 60,
+            posts_per_bit: 30,
+        }
+    }
+}
+
+/// how long threads live on root, none is no limit.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub(crate) struct Threads {
+    /// most threads on root, the least recently bumped go to archive.
+    pub max_live: Option<usize>,
+    /// replies after so many do not bump.
+    pub bump_limit: Option<usize>,
+    /// threads lock at so many replies.
+    pub reply_cap: Option<usize>,
+    /// days archived threads are kept, forever if not set.
+    pub archive_days: Option<i64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen: vec![SocketAddr::from(([0u16; 8], 1120))],
+            database: PathBuf::from("database"),
+            name: "terminus".to_owned(),
+            log: Log::default(),
+            tls_cert: None,
+            tls_key: None,
+            delete_window_minutes: 5 * 60,
+            retention_days: 7,
+            edit_window_minutes: None,
+            max_frame: DEFAULT_MAX_FRAME,
+            limits: Limits::default(),
+            moderators: Vec::new(),
+            announcement: None,
+            rate_limits: RateLimits::default(),
+            proof_of_work: ProofOfWork::default(),
+            filter_rules: None,
+            threads: Threads::default(),
+        }
+    }
+}
+
+/// settings given on command line, over those in file.
+#[derive(StructOpt, Debug, Clone)]
+pub(crate) struct Overrides {
+    /// address to listen, can be given more than once.
+    #[structopt(short, long)]
+    listen: Vec<SocketAddr>,
+    #[structopt(long, parse(from_os_str))]
+    database: Option<PathBuf>,
+    #[structopt(long, env = "TERMINUS_NAME")]
+    name: Option<String>,
+    /// stdout, stderr or a file path.
+    #[structopt(long)]
+    log_target: Option<String>,
+    #[structopt(long)]
+    log_level: Option<String>,
+    #[structopt(long, env = "TERMINUS_TLS_CERT", parse(from_os_str))]
+    tls_cert: Option<PathBuf>,
+    #[structopt(long, env = "TERMINUS_TLS_KEY", parse(from_os_str))]
+    tls_key: Option<PathBuf>,
+    #[structopt(long)]
+    delete_window_minutes: Option<i64>,
+    #[structopt(long)]
+    edit_window_minutes: Option<i64>,
+    #[structopt(long)]
+    max_frame: Option<u32>,
+    #[structopt(long)]
+    title_limit: Option<usize>,
+    #[structopt(long)]
+    content_limit: Option<usize>,
+    #[structopt(long)]
+    line_limit: Option<usize>,
+    #[structopt(long)]
+    depth_limit: Option<usize>,
+    #[structopt(long)]
+    announcement: Option<String>,
+    /// bits of proof of work when quiet, zero turns it off.
+    #[structopt(long)]
+    difficulty: Option<u32>,
+    #[structopt(long, parse(from_os_str))]
+    filter_rules: Option<PathBuf>,
+    /// most threads on root, the rest go to archive.
+    #[structopt(long)]
+    max_threads: Option<usize>,
+}
+
+impl Overrides {
+    fn apply(&self, config: &mut Config) {
+        if !self.listen.is_empty() {
+            config.listen = self.listen.clone();
+        }
+        config.database = self.database.clone().unwrap_or_else(|| config.database.clone());
+        config.name = self.name.clone().unwrap_or_else(|| config.name.clone());
+        config.log.target = self.log_target.clone().unwrap_or_else(|| config.log.target.clone());
+        config.log.level = self.log_level.clone().unwrap_or_else(|| config.log.level.clone());
+        config.tls_cert = self.tls_cert.clone().or_else(|| config.tls_cert.clone());
+        config.tls_key = self.tls_key.clone().or_else(|| config.tls_key.clone());
+        config.delete_window_minutes = self.delete_window_minutes.unwrap_or(config.delete_window_minutes);
+        config.edit_window_minutes = self.edit_window_minutes.or(config.edit_window_minutes);
+        config.max_frame = self.max_frame.unwrap_or(config.max_frame);
+        config.limits.title = self.title_limit.unwrap_or(config.limits.title);
+        config.limits.content = self.content_limit.unwrap_or(config.limits.content);
+        config.limits.lines = self.line_limit.unwrap_or(config.limits.lines);
+        config.limits.depth = self.depth_limit.unwrap_or(config.limits.depth);
+        config.announcement = self.announcement.clone().or_else(|| config.announcement.clone());
+        config.proof_of_work.difficulty = self.difficulty.unwrap_or(config.proof_of_work.difficulty);
+        config.filter_rules = self.filter_rules.clone().or_else(|| config.filter_rules.clone());
+        config.threads.max_live = self.max_threads.or(config.threads.max_live);
+    }
+}
+
+impl Config {
+    /// defaults if no file given, command line wins.
+    pub(crate) fn load(path: Option<&Path>, overrides: &Overrides) -> anyhow::Result<Self> {
+        let mut config = match path {
+            Some(path) => toml::from_str(&fs::read_to_string(path)?)?,
+            None => Self::default(),
+        };
+        overrides.apply(&mut config);
+        if config.listen.is_empty() {
+            anyhow::bail!("nothing to listen");
+        }
+        config.log_level()?;
+        Ok(config)
+    }
+
+    pub(crate) fn log_level(&self) -> anyhow::Result<log::LevelFilter> {
+        log::LevelFilter::from_str(&self.log.level).map_err(|_| anyhow::anyhow!("log level {} unknown", self.log.level))
+    }
+
+    pub(crate) fn delete_window(&self) -> Duration {
+        Duration::minutes(self.delete_window_minutes)
+    }
+
+    pub(crate) fn retention(&self) -> Duration {
+        Duration::days(self.retention_days)
+    }
+
+    pub(crate) fn edit_window(&self) -> Option<Duration> {
+        self.edit_window_minutes.map(Duration::minutes)
+    }
+
+    pub(crate) fn archive_keep(&self) -> Option<Duration> {
+        self.threads.archive_days.map(Duration::days)
+    }
+
+    /// take what can change at runtime from new, keep the rest.
+    pub(crate) fn reload(&self, new: Self) -> Self {
+        let restart = [
+            ("listen", self.listen != new.listen),
+            ("database", self.database != new.database),
+            ("log target", self.log.target != new.log.target),
+            ("tls", (&self.tls_cert, &self.tls_key) != (&new.tls_cert, &new.tls_key)),
+        ];
+        for (name, changed) in restart.iter() {
+            if *changed {
+                log::warn!("[config] {} changed, takes effect after restart.", name);
+            }
+        }
+        Self {
+            listen: self.listen.clone(),
+            database: self.database.clone(),
+            log: Log {
+                target: self.log.target.clone(),
+                level: new.log.level.clone(),
+            },
+            tls_cert: self.tls_cert.clone(),
+            tls_key: self.tls_key.clone(),
+            ..new
+        }
+    }
+}
+
+type Channel = (watch::Sender<Arc<Config>>, watch::Receiver<Arc<Config>>);
+
+/// settings in effect, links wait on it for what clients should know.
+static CURRENT: Lazy<Channel> = Lazy::new(|| watch::channel(Default::default()));
+
+pub(crate) fn current() -> Arc<Config> {
+    CURRENT.1.borrow().clone()
+}
+
+pub(crate) fn set(config: Config) {
+    // a receiver is kept here, never fails
+    CURRENT.0.send(Arc::new(config)).ok();
+}
+
+/// changes with every set.
+pub(crate) fn watch() -> watch::Receiver<Arc<Config>> {
+    CURRENT.1.clone()
+}
+
+/// log at every level, the real level is the max level so it can reload.
+pub(crate) fn init_log(config: &Config) -> anyhow::Result<()> {
+    let all = simplelog::LevelFilter::Trace;
+    let log_config = simplelog::ConfigBuilder::new().set_time_format_str("%+").build();
+    let color_choice = simplelog::ColorChoice::Auto;
+    match config.log.target.as_str() {
+        "stdout" => simplelog::TermLogger::init(all, log_config, simplelog::TerminalMode::Stdout, color_choice)?,
+        "stderr" => simplelog::TermLogger::init(all, log_config, simplelog::TerminalMode::Stderr, color_choice)?,
+        path => {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            simplelog::WriteLogger::init(all, log_config, file)?
+        }
+    }
+    log::set_max_level(config.log_level()?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, Overrides, SocketAddr};
+    use structopt::StructOpt;
+
+    #[test]
+    fn file_and_overrides() {
+        let mut config: Config = toml::from_str(
+            r#"
+            listen = ["0.0.0.0:1120", "[::1]:1121"]
+            name = "board"
+            edit_window_minutes = 10
+
+            [log]
+            level = "warn"
+
+            [limits]
+            title = 64
+
+            [threads]
+            bump_limit = 300
+            "#,
+        )
+        .unwrap();
+        assert_eq!(2, config.listen.len());
+        assert_eq!(Some(10), config.edit_window_minutes);
+        assert_eq!(5 * 60, config.delete_window_minutes);
+        assert_eq!("stdout", config.log.target);
+        assert_eq!(64, config.limits.title);
+        assert_eq!((Some(300), None), (config.threads.bump_limit, config.threads.max_live));
+        let overrides = Overrides::from_iter(&[
+            "test",
+            "-l",
+            "127.0.0.1:8000",
+            "--title-limit",
+            "32",
+            "--max-threads",
+            "9",
+        ]);
+        overrides.apply(&mut config);
+        assert_eq!(vec!["127.0.0.1:8000".parse::<SocketAddr>().unwrap()], config.listen);
+        assert_eq!(32, config.limits.title);
+        assert_eq!(Some(9), config.threads.max_live);
+        assert_eq!("board", config.name);
+    }
+
+    #[test]
+    fn reload_keeps_restart_settings() {
+        let old = Config::default();
+        let mut new = Config {
+            listen: vec!["127.0.0.1:1".parse().unwrap()],
+            delete_window_minutes: 1,
+            ..Config::default()
+        };
+        new.log.level = "debug".to_owned();
+        let config = old.reload(new);
+        assert_eq!(old.listen, config.listen);
+        assert_eq!("debug", config.log.level);
+        assert_eq!(1, config.delete_window_minutes);
+    }
+}
@@ -0,0 +1,195 @@
+//! content filter, rules from a toml file applied to posts and edits.
+
+use crate::config;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Deserialize;
+use std::{
+    fs,
+    sync::{Arc, RwLock},
+};
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Kind {
+    Reject,
+    Replace,
+    Hold,
+}
+
+/// a rule as written in file, either pattern or words is given.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct RuleFile {
+    name: String,
+    /// regex, see the regex crate for syntax.
+    pattern: Option<String>,
+    /// whole words, case insensitive.
+    #[serde(default)]
+    words: Vec<String>,
+    action: Kind,
+    /// told to poster on reject, and to moderators on hold.
+    #[serde(default)]
+    reason: String,
+    /// text put in place of matches, `$1` for groups.
+    #[serde(default)]
+    replace: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct RulesFile {
+    #[serde(default)]
+    rule: Vec<RuleFile>,
+}
+
+struct Rule {
+    name: String,
+    regex: Regex,
+    kind: Kind,
+    reason: String,
+    replace: String,
+}
+
+impl Rule {
+    fn compile(rule: RuleFile) -> anyhow::Result<Self> {
+        let pattern = match (rule.pattern.clone(), rule.words.is_empty()) {
+            (Some(pattern), true) => pattern,
+            (None, false) => {
+                let words: Vec<_> = rule.words.iter().map(|word| regex::escape(word)).collect();
+                format!(r"(?i)\b(?:{})\b", words.join("|"))
+            }
+            _ => anyhow::bail!("rule {} should have one of pattern and words", rule.name),
+        };
+        let regex = Regex::new(&pattern).map_err(|e| anyhow::anyhow!("rule {}: {}", rule.name, e))?;
+        let reason = if rule.reason.is_empty() {
+            format!("filtered by {}", rule.name)
+        } else {
+            rule.reason
+        };
+        Ok(Self {
+            name: rule.name,
+            regex,
+            kind: rule.action,
+            reason,
+            replace: rule.replace,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Verdict {
+    Pass,
+    Reject(String),
+    Hold(String),
+}
+
+/// what the rules say, and names of those matched.
+#[derive(Debug)]
+pub(crate) struct Outcome {
+    pub verdict: Verdict,
+    pub hits: Vec<String>,
+}
+
+fn parse(text: &str) -> anyhow::Result<Vec<Rule>> {
+    let file: RulesFile = toml::from_str(text)?;
+    file.rule.into_iter().map(Rule::compile).collect()
+}
+
+/// rules in order, later ones see what earlier ones replaced.
+fn run(rules: &[Rule], fields: &mut [&mut String]) -> Outcome {
+    let mut verdict = Verdict::Pass;
+    let mut hits = Vec::new();
+    for rule in rules.iter() {
+        if !fields.iter().any(|field| rule.regex.is_match(field)) {
+            continue;
+        }
+        hits.push(rule.name.clone());
+        match rule.kind {
+            Kind::Reject => {
+                verdict = Verdict::Reject(rule.reason.clone());
+                break;
+            }
+            Kind::Replace => {
+                for field in fields.iter_mut() {
+                    let replaced = rule.regex.replace_all(field, rule.replace.as_str()).into_owned();
+                    **field = replaced;
+                }
+            }
+            // first hold is kept, a later reject still wins
+            Kind::Hold => {
+                if verdict == Verdict::Pass {
+                    verdict = Verdict::Hold(rule.reason.clone());
+                }
+            }
+        }
+    }
+    Outcome { verdict, hits }
+}
+
+static RULES: Lazy<RwLock<Arc<Vec<Rule>>>> = Lazy::new(Default::default);
+
+/// read rules file in config, none if not set.
+/// old rules are kept if the file is broken.
+pub(crate) fn reload() -> anyhow::Result<usize> {
+    let rules = match config::current().filter_rules {
+        Some(ref path) => parse(&fs::read_to_string(path)?)?,
+        None => Vec::new(),
+    };
+    let count = rules.len();
+    *RULES.write().unwrap() = Arc::new(rules);
+    Ok(count)
+}
+
+/// filter fields in place, title and content of a post or the fields of an edit.
+pub(crate) fn apply(fields: &mut [&mut String]) -> Outcome {
+    let rules = RULES.read().unwrap().clone();
+    run(&rules, fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, run, Verdict};
+
+    const RULES: &str = r#"
+        [[rule]]
+        name = "words"
+        words = ["heck", "darn"]
+        action = "replace"
+        replace = "****"
+
+        [[rule]]
+        name = "links"
+        pattern = "https?://"
+        action = "reject"
+        reason = "no links"
+
+        [[rule]]
+        name = "money"
+        pattern = "(?i)casino"
+        action = "hold"
+    "#;
+
+    #[test]
+    fn rules() {
+        let rules = parse(RULES).unwrap();
+        let (mut title, mut content) = ("Heck".to_owned(), "darned darn".to_owned());
+        let outcome = run(&rules, &mut [&mut title, &mut content]);
+        assert_eq!(Verdict::Pass, outcome.verdict);
+        assert_eq!(vec!["words"], outcome.hits);
+        assert_eq!(("****", "darned ****"), (title.as_str(), content.as_str()));
+        let mut content = "CASINO at https://x".to_owned();
+        let outcome = run(&rules, &mut [&mut content]);
+        assert_eq!(Verdict::Reject("no links".to_owned()), outcome.verdict);
+        let mut content = "casino".to_owned();
+        let outcome = run(&rules, &mut [&mut content]);
+        assert_eq!(Verdict::Hold("filtered by money".to_owned()), outcome.verdict);
+    }
+
+    #[test]
+    fn broken_rules() {
+        assert!(parse("[[rule]]\nname = \"x\"\naction = \"reject\"").is_err());
+        assert!(parse("[[rule]]\nname = \"x\"\npattern = \"(\"\naction = \"reject\"").is_err());
+        assert!(parse("[[rule]]\nname = \"x\"\npattern = \"x\"\naction = \"shout\"").is_err());
+        assert!(parse("").unwrap().is_empty());
+    }
+}
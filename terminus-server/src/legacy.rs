@@ -0,0 +1,127 @@
+//! wire format of clients never say hello, which send bare actions.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use terminus_types::{action, Author, Draft, Error, Patch};
+
+/// node as they know it, without flags added later.
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct Node {
+    id: Vec<u8>,
+    title: String,
+    author: Author,
+    content: String,
+    publish_time: DateTime<Utc>,
+    last_reply: DateTime<Utc>,
+    edited: bool,
+}
+
+impl From<Node> for terminus_types::Node {
+    fn from(node: Node) -> Self {
+        Self {
+            id: node.id,
+            title: node.title,
+            author: node.author,
+            content: node.content,
+            publish_time: node.publish_time,
+            last_reply: node.last_reply,
+            edited: node.edited,
+            sticky: false,
+            deleted: false,
+        }
+    }
+}
+
+impl From<terminus_types::Node> for Node {
+    fn from(node: terminus_types::Node) -> Self {
+        Self {
+            id: node.id,
+            title: node.title,
+            author: node.author,
+            content: node.content,
+            publish_time: node.publish_time,
+            last_reply: node.last_reply,
+            edited: node.edited,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) enum Action {
+    Delete(Node),
+    List(action::ListTarget),
+    Update(Node),
+    Post(Node),
+}
+
+impl From<Action> for action::Action {
+    fn from(action: Action) -> Self {
+        match action {
+            Action::Delete(node) => Self::Delete(node.into()),
+            // no paging, they want it all
+            Action::List(target) => Self::List {
+                target,
+                limit: u32::MAX,
+                cursor: None,
+            },
+            // they send the whole node, unchanged fields are ignored by update
+            Action::Update(node) => Self::Update(Patch {
+                id: node.id,
+                author: node.author,
+                title: Some(node.title),
+                content: Some(node.content),
+            }),
+            // their id is dropped, the server gives a new one
+            Action::Post(node) => {
+                let node = terminus_types::Node::from(node);
+                let parent = node.parent_id().to_owned();
+                Self::Post(Draft::new(&parent, node.title, node.author, node.content))
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) enum Response {
+    Post(Node),
+    Update(Node),
+    Delete(Node),
+    List(Vec<Node>),
+    Err(Error),
+}
+
+impl Response {
+    /// responses they can not understand are dropped.
+    pub(crate) fn from_response(response: action::Response) -> Option<Self> {
+        match response {
+            action::Response::Post(node) => Some(Self::Post(node.into())),
+            action::Response::Update(node) => Some(Self::Update(node.into())),
+            action::Response::Delete(node) => Some(Self::Delete(node.into())),
+            action::Response::List { nodes, .. } => Some(Self::List(nodes.into_iter().map(Node::from).collect())),
+            action::Response::Err(e) => Some(Self::Err(legacy_error(e))),
+            action::Response::Subscribe(_)
+            | action::Response::Unsubscribe(_)
+            | action::Response::Moderate(_)
+            | action::Response::Announcement(_)
+            | action::Response::Limits(_)
+            | action::Response::Difficulty(_)
+            | action::Response::Held(_)
+            | action::Response::History { .. }
+            | action::Response::Stats(_) => None,
+        }
+    }
+}
+
+/// errors they do not know come as a network error, so every request still gets its reply.
+fn legacy_error(e: Error) -> Error {
+    match e {
+        Error::NeedUnMaskPass
+        | Error::NodeExist
+        | Error::NodeNotExist
+        | Error::IdInvalid
+        | Error::PassNotMatch
+        | Error::DeleteLimitOverdue
+        | Error::NetworkError => e,
+        _ => Error::NetworkError,
+    }
+}
@@ -0,0 +1,131 @@
+//! token buckets against flooding, kept in memory.
+
+use crate::config::{self, Rate};
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use terminus_types::Error;
+
+/// buckets kept before full ones are dropped.
+const PRUNE_AT: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Who {
+    Address(IpAddr),
+    Tripcode(String),
+}
+
+struct Bucket {
+    tokens: f64,
+    since: Instant,
+}
+
+impl Bucket {
+    fn full(rate: &Rate, now: Instant) -> Self {
+        Self {
+            tokens: rate.burst as f64,
+            since: now,
+        }
+    }
+
+    /// gain tokens for time passed, up to burst.
+    fn refill(&mut self, rate: &Rate, now: Instant) {
+        let burst = rate.burst as f64;
+        if rate.interval_seconds == 0 {
+            self.tokens = burst.max(1.0);
+        } else {
+            let gained = now.saturating_duration_since(self.since).as_secs_f64() / rate.interval_seconds as f64;
+            self.tokens = (self.tokens + gained).min(burst);
+        }
+        self.since = now;
+    }
+
+    /// time until a token is here, zero if one is.
+    fn wait(&self, rate: &Rate) -> Duration {
+        if self.tokens >= 1.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64((1.0 - self.tokens) * rate.interval_seconds as f64)
+    }
+}
+
+fn rate_of(limits: &config::RateLimits, who: &Who, is_thread: bool) -> Rate {
+    match (who, is_thread) {
+        (Who::Address(_), true) => limits.address_threads.clone(),
+        (Who::Address(_), false) => limits.address_replies.clone(),
+        (Who::Tripcode(_), true) => limits.tripcode_threads.clone(),
+        (Who::Tripcode(_), false) => limits.tripcode_replies.clone(),
+    }
+}
+
+static BUCKETS: Lazy<Mutex<HashMap<(Who, bool), Bucket>>> = Lazy::new(Default::default);
+
+/// take a token from both buckets of a post, or tell how long to wait.
+pub(crate) fn take(address: IpAddr, tripcode: &str, is_thread: bool) -> Result<(), Error> {
+    let config = config::current();
+    let now = Instant::now();
+    let mut buckets = BUCKETS.lock().unwrap();
+    if buckets.len() > PRUNE_AT {
+        buckets.retain(|(who, is_thread), bucket| {
+            let rate = rate_of(&config.rate_limits, who, *is_thread);
+            bucket.refill(&rate, now);
+            bucket.tokens < rate.burst as f64
+        });
+    }
+    let keys = [
+        (Who::Address(address), is_thread),
+        (Who::Tripcode(tripcode.to_owned()), is_thread),
+    ];
+    let mut wait = Duration::ZERO;
+    for key in keys.iter() {
+        let rate = rate_of(&config.rate_limits, &key.0, is_thread);
+        let bucket = buckets.entry(key.clone()).or_insert_with(|| Bucket::full(&rate, now));
+        bucket.refill(&rate, now);
+        wait = wait.max(bucket.wait(&rate));
+    }
+    if wait > Duration::ZERO {
+        // whole seconds, never tell a wait of zero
+        let retry_after = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+        return Err(Error::RateLimited { retry_after });
+    }
+    // both have one, or none is taken
+    for key in keys.iter() {
+        if let Some(bucket) = buckets.get_mut(key) {
+            bucket.tokens -= 1.0;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bucket, Rate};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn bucket() {
+        let rate = Rate {
+            burst: 2,
+            interval_seconds: 10,
+        };
+        let start = Instant::now();
+        let mut bucket = Bucket::full(&rate, start);
+        bucket.tokens -= 2.0;
+        assert_eq!(Duration::from_secs(10), bucket.wait(&rate));
+        bucket.refill(&rate, start + Duration::from_secs(5));
+        assert_eq!(Duration::from_secs(5), bucket.wait(&rate));
+        bucket.refill(&rate, start + Duration::from_secs(100));
+        assert_eq!(2.0, bucket.tokens);
+        let free = Rate {
+            burst: 0,
+            interval_seconds: 0,
+        };
+        bucket.tokens = -1.0;
+        bucket.refill(&free, start);
+        assert_eq!(Duration::ZERO, bucket.wait(&free));
+    }
+}
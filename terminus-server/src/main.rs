@@ -1,83 +1,464 @@
-use crossbeam_channel::Receiver;
-use terminus_types::action::{Action, ListTarget, Response};
+use config::{Config, Overrides};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter},
+    net::IpAddr,
+    path::PathBuf,
+};
+use structopt::StructOpt;
+use terminus_types::{
+    action::{capability, Action, Envelope, Hello, ListTarget, Moderation, RequestId, Response},
+    codec::{FrameError, FrameReader, FrameWriter},
+    Author, Draft, Error, NodeId, ID_LEN,
+};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::{tcp::OwnedWriteHalf, TcpListener, TcpStream},
+    io::{AsyncRead, AsyncWrite, WriteHalf},
+    net::TcpListener,
+    signal::unix::{signal, SignalKind},
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    task::JoinHandle,
 };
+use tokio_rustls::TlsAcceptor;
 
+mod config;
+mod filter;
+mod legacy;
+mod limit;
+mod stamp;
 mod store;
+mod tls;
+
+/// most nodes in one list page.
+const PAGE_LIMIT: u32 = 200;
+/// most targets one link watches at once.
+const MAX_SUBSCRIPTIONS: usize = 64;
+const CAPABILITIES: &[&str] = &[capability::NOTIFY, capability::ENVELOPE, capability::SUBSCRIBE];
+
+/// watches a link subscribed, stopped on drop.
+struct Subscriptions {
+    s: UnboundedSender<Envelope>,
+    watches: HashMap<ListTarget, JoinHandle<anyhow::Result<()>>>,
+}
+
+impl Subscriptions {
+    fn new(s: UnboundedSender<Envelope>) -> Self {
+        Self {
+            s,
+            watches: HashMap::new(),
+        }
+    }
+
+    /// only whole node ids are watched, up to [`MAX_SUBSCRIPTIONS`].
+    fn subscribe(&mut self, target: ListTarget) -> Result<(), Error> {
+        if let ListTarget::Node(id) = &target {
+            if id.is_empty() || !id.len().is_multiple_of(ID_LEN) {
+                return Err(Error::IdInvalid);
+            }
+        }
+        if self.watches.len() >= MAX_SUBSCRIPTIONS && !self.watches.contains_key(&target) {
+            return Err(Error::TooManySubscriptions);
+        }
+        let s = &self.s;
+        self.watches
+            .entry(target.clone())
+            .or_insert_with(|| tokio::spawn(store::watch(target, s.clone())));
+        Ok(())
+    }
+
+    fn unsubscribe(&mut self, target: &ListTarget) {
+        if let Some(watch) = self.watches.remove(target) {
+            watch.abort();
+        }
+    }
+}
+
+impl Drop for Subscriptions {
+    fn drop(&mut self) {
+        for (_target, watch) in self.watches.drain() {
+            watch.abort();
+        }
+    }
+}
+
+fn post(draft: Draft, sage: bool, peer: &Peer) -> anyhow::Result<Response> {
+    let work = match stamp::check(&draft) {
+        Ok(work) => work,
+        Err(e) => return Ok(Response::Err(e)),
+    };
+    let tripcode = store::key_of(draft.author.clone());
+    if let Err(e) = limit::take(peer.address, &tripcode, draft.parent.is_empty()) {
+        stamp::release(work);
+        return Ok(Response::Err(e));
+    }
+    let response = store::post(draft, sage, peer.address);
+    match response {
+        Ok(Response::Post(_)) => stamp::posted(),
+        // held posts keep their stamp, or one stamp could fill the queue
+        Ok(Response::Err(Error::Held)) => {}
+        _ => stamp::release(work),
+    }
+    response
+}
+
+/// stats of threads in a page go ahead of it.
+fn with_stats(response: anyhow::Result<Response>, subs: &Subscriptions) -> anyhow::Result<Response> {
+    if let Ok(Response::List { nodes, .. }) = &response {
+        subs.s.send(Envelope::Event(Response::Stats(store::stats_of(nodes)?)))?;
+    }
+    response
+}
 
-fn take_action(action: Action) -> anyhow::Result<Response> {
+fn take_action(action: Action, peer: &Peer, subs: &mut Subscriptions) -> anyhow::Result<Response> {
     match action {
-        Action::Post(node) => store::post(node),
+        Action::Post(draft) => post(draft, false, peer),
+        Action::Sage(draft) => post(draft, true, peer),
         Action::Delete(node) => store::delete(node),
-        Action::Update(node) => store::update(node),
-        Action::List(ListTarget::Root) => store::list_root(),
-        Action::List(ListTarget::Node(node_id)) => store::list(node_id),
+        Action::Update(patch) => store::update(patch),
+        Action::List {
+            target: ListTarget::Root,
+            limit,
+            cursor,
+        } => with_stats(store::list_root(limit as usize, cursor), subs),
+        Action::List {
+            target: ListTarget::Node(node_id),
+            limit,
+            cursor,
+        } => store::list(node_id, limit as usize, cursor),
+        Action::List {
+            target: ListTarget::Archive,
+            limit,
+            cursor,
+        } => with_stats(store::list_archive(limit as usize, cursor), subs),
+        Action::Subscribe(target) => match subs.subscribe(target.clone()) {
+            Ok(()) => Ok(Response::Subscribe(target)),
+            Err(e) => Ok(Response::Err(e)),
+        },
+        Action::Unsubscribe(target) => {
+            subs.unsubscribe(&target);
+            Ok(Response::Unsubscribe(target))
+        }
+        Action::Moderate { author, action, reason } => store::moderate(author, action, reason),
+        Action::History(id) => store::history(id),
     }
 }
 
-async fn send(mut send: OwnedWriteHalf, recv: Receiver<Response>) -> anyhow::Result<()> {
-    loop {
-        let resp = recv.recv()?;
-        let size = bincode::serialized_size(&resp)? as u32;
-        let size = bincode::serialize(&size)?;
-        let data = bincode::serialize(&resp)?;
-        send.write_all(&size).await?;
-        send.write_all(&data).await?;
-    }
-}
-
-async fn handle(link: TcpStream) -> anyhow::Result<()> {
-    let mut indicator = [0u8; 4];
-    let mut buf = Vec::new();
-    let (mut r, s) = link.into_split();
-    let (client_s, client_r) = crossbeam_channel::unbounded();
-    let to_client = tokio::spawn(send(s, client_r));
-    let inbox = tokio::spawn(store::notify_channel(client_s.clone()));
-    loop {
-        r.read_exact(&mut indicator).await?;
-        let size: u32 = bincode::deserialize(&indicator)?;
-        if size == 0 {
-            log::info!("end signal received.");
-            to_client.abort();
-            inbox.abort();
+async fn send<S: AsyncWrite>(
+    mut send: FrameWriter<WriteHalf<S>>,
+    mut recv: UnboundedReceiver<Envelope>,
+    envelope: bool,
+) -> anyhow::Result<()> {
+    while let Some(frame) = recv.recv().await {
+        let id = match frame {
+            Envelope::Reply { id, .. } => Some(id),
+            _ => None,
+        };
+        let res = if envelope {
+            send.write(&frame).await
+        } else if let Some(resp) = frame.into_response().and_then(legacy::Response::from_response) {
+            send.write(&resp).await
+        } else {
+            continue;
+        };
+        match res {
+            Ok(()) => {}
+            Err(FrameError::Io(e)) => return Err(e.into()),
+            // nothing written, link still fine
+            Err(e) => {
+                log::warn!("response dropped: {}.", e);
+                if let (Some(id), true) = (id, envelope) {
+                    let response = Response::Err(Error::FrameTooLarge);
+                    send.write(&Envelope::Reply { id, response }).await?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// a request we can not take, id is none if even that can not be read.
+type Rejected = (Option<RequestId>, Error);
+
+/// every request gets a reply, success or not.
+fn respond(
+    request: Result<(RequestId, Action), Rejected>,
+    peer: &Peer,
+    subs: &mut Subscriptions,
+) -> anyhow::Result<()> {
+    let (id, action) = match request {
+        Ok(request) => request,
+        Err((id, e)) => {
+            log::warn!("[handle] request rejected: {}.", e);
+            let response = Response::Err(e);
+            let frame = match id {
+                Some(id) => Envelope::Reply { id, response },
+                None => Envelope::Event(response),
+            };
+            subs.s.send(frame)?;
             return Ok(());
         }
-        buf.resize(size as usize, 0u8);
-        r.read_exact(&mut buf).await?;
-        let action: Action = bincode::deserialize(&buf)?;
-        match take_action(action) {
-            Ok(resp) => {
-                if let Response::Err(e) = &resp {
-                    log::warn!("[handle] node handle failed: {}.", e);
-                }
-                client_s.send(resp)?;
+    };
+    let response = match take_action(action, peer, subs) {
+        Ok(response) => {
+            if let Response::Err(e) = &response {
+                log::warn!("[handle] node handle failed: {}.", e);
             }
-            Err(e) => {
-                log::warn!("can not deal request: {}", e);
+            response
+        }
+        Err(e) => {
+            log::error!("can not deal request: {}", e);
+            Response::Err(e.downcast::<Error>().unwrap_or(Error::Internal))
+        }
+    };
+    subs.s.send(Envelope::Reply { id, response })?;
+    Ok(())
+}
+
+/// who the peer is and what it can deal with.
+struct Peer {
+    address: IpAddr,
+    notify: bool,
+    envelope: bool,
+    subscribe: bool,
+}
+
+impl Peer {
+    /// peer never say hello.
+    fn legacy(address: IpAddr) -> Self {
+        Self {
+            address,
+            notify: true,
+            envelope: false,
+            subscribe: false,
+        }
+    }
+
+    fn from_hello(hello: &Hello, address: IpAddr) -> Self {
+        Self {
+            address,
+            notify: hello.supports(capability::NOTIFY),
+            envelope: hello.supports(capability::ENVELOPE),
+            subscribe: hello.supports(capability::SUBSCRIBE),
+        }
+    }
+
+    fn read_request(&self, frame: &[u8]) -> Result<(RequestId, Action), Rejected> {
+        // not capped, without cursor they can not ask for the next page
+        if !self.envelope {
+            return match bincode::deserialize::<legacy::Action>(frame) {
+                Ok(action) => Ok((0, action.into())),
+                Err(_) => Err((None, Error::Malformed)),
+            };
+        }
+        let envelope = bincode::deserialize(frame).map_err(|_| (Envelope::peek_id(frame), Error::Malformed))?;
+        match envelope {
+            Envelope::Request {
+                id,
+                action: Action::List { target, limit, cursor },
+            } => {
+                let limit = limit.min(PAGE_LIMIT);
+                Ok((id, Action::List { target, limit, cursor }))
+            }
+            Envelope::Request { id, action } => Ok((id, action)),
+            other => {
+                log::warn!("unexpected frame from client: {:?}.", other);
+                Err((None, Error::Unsupported))
             }
         }
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // logs
-    log_panics::init();
-    let log_level = simplelog::LevelFilter::Info;
-    let log_config = simplelog::ConfigBuilder::new().set_time_format_str("%+").build();
-    let term_mode = simplelog::TerminalMode::Stdout;
-    let color_choice = simplelog::ColorChoice::Auto;
-    simplelog::TermLogger::init(log_level, log_config, term_mode, color_choice).expect("log set failed");
+/// tell limits, announcement and difficulty with salt on link, and again whenever they change.
+async fn tell_board(s: UnboundedSender<Envelope>) -> anyhow::Result<()> {
+    let mut config = config::watch();
+    let mut difficulty = stamp::watch();
+    let mut limits = None;
+    let mut announcement = None;
+    let mut told_difficulty = None;
+    loop {
+        let current = config.borrow().clone();
+        if limits.as_ref() != Some(&current.limits) {
+            s.send(Envelope::Event(Response::Limits(current.limits.clone())))?;
+            limits = Some(current.limits.clone());
+        }
+        if announcement != current.announcement {
+            s.send(Envelope::Event(Response::Announcement(current.announcement.clone())))?;
+            announcement = current.announcement.clone();
+        }
+        let current_difficulty = *difficulty.borrow();
+        if told_difficulty != Some(current_difficulty) {
+            s.send(Envelope::Event(Response::Difficulty(current_difficulty)))?;
+            told_difficulty = Some(current_difficulty);
+        }
+        let changed = tokio::select! {
+            changed = config.changed() => changed,
+            changed = difficulty.changed() => changed,
+        };
+        if changed.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+async fn handle<S>(link: S, address: IpAddr) -> anyhow::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let config = config::current();
+    let (r, s) = tokio::io::split(link);
+    let mut r = FrameReader::new(r, config.max_frame);
+    let mut s = FrameWriter::new(s, config.max_frame);
+    let frame = match r.next_frame().await? {
+        Some(frame) => frame,
+        None => {
+            log::info!("end signal received.");
+            return Ok(());
+        }
+    };
+    // the first frame is hello, or an action from legacy client.
+    let mut first = None;
+    let peer = if Hello::is_hello(frame) {
+        let hello: Hello = bincode::deserialize(frame)?;
+        log::info!("hello from {} v{}.", hello.name, hello.version);
+        s.write(&Hello::new(&config.name, CAPABILITIES)).await?;
+        if !hello.is_compatible() {
+            log::warn!("client version {} not supported.", hello.version);
+            s.finish().await?;
+            return Ok(());
+        }
+        Peer::from_hello(&hello, address)
+    } else {
+        log::info!("legacy client without hello.");
+        let peer = Peer::legacy(address);
+        first = Some(peer.read_request(frame));
+        peer
+    };
+    let (client_s, client_r) = mpsc::unbounded_channel();
+    let to_client = tokio::spawn(send(s, client_r, peer.envelope));
+    // peer can not subscribe gets everything.
+    let inbox = if peer.notify && !peer.subscribe {
+        Some(tokio::spawn(store::notify_channel(client_s.clone())))
+    } else {
+        None
+    };
+    let teller = tokio::spawn(tell_board(client_s.clone()));
+    let mut subs = Subscriptions::new(client_s);
+    if let Some(request) = first {
+        respond(request, &peer, &mut subs)?;
+    }
+    while let Some(frame) = r.next_frame().await? {
+        respond(peer.read_request(frame), &peer, &mut subs)?;
+    }
+    log::info!("end signal received.");
+    to_client.abort();
+    teller.abort();
+    if let Some(inbox) = inbox {
+        inbox.abort();
+    }
+    Ok(())
+}
+
+#[derive(StructOpt)]
+#[structopt(about = "terminus board server")]
+struct Opt {
+    /// toml config file, defaults are used if not given.
+    #[structopt(short, long, env = "TERMINUS_CONFIG", parse(from_os_str))]
+    config: Option<PathBuf>,
+    #[structopt(flatten)]
+    overrides: Overrides,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// serve the board, the default.
+    Serve,
+    /// check the database and repair drift, with the server stopped.
+    Fsck {
+        /// only report, change nothing.
+        #[structopt(long)]
+        dry_run: bool,
+    },
+    /// upgrade the database to the latest version, with the server stopped.
+    Migrate {
+        /// only report, change nothing.
+        #[structopt(long)]
+        dry_run: bool,
+    },
+    /// dump the board as json lines, nodes in id order and all else the board keeps after.
+    Export {
+        /// write here instead of stdout.
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// restore an export in one go, keeping ids.
+    Import {
+        /// read here instead of stdin.
+        #[structopt(short, long, parse(from_os_str))]
+        input: Option<PathBuf>,
+    },
+    /// count threads and replies, and show the busiest threads.
+    Stats {
+        /// how many busiest threads to show.
+        #[structopt(long, default_value = "10")]
+        top: usize,
+    },
+    /// rewrite the database to reclaim space, refused while the server has it open.
+    Compact,
+    /// print the moderator key of name, pass is read from stdin.
+    Key { name: String },
+    /// show what moderators did.
+    ModLog {
+        /// how many last records to show.
+        #[structopt(long, default_value = "50")]
+        last: usize,
+    },
+    /// show posts and edits held by filter.
+    Held,
+    /// put a held post or edit on board, with the server stopped.
+    Approve {
+        /// id as `held` shows it.
+        id: u128,
+        #[structopt(long, default_value = "")]
+        reason: String,
+    },
+    /// drop a held post or edit, with the server stopped.
+    Discard {
+        /// id as `held` shows it.
+        id: u128,
+        #[structopt(long, default_value = "")]
+        reason: String,
+    },
+}
 
-    let listener = TcpListener::bind("[::]:1120").await?;
+/// approve or discard a held node by the id `held` shows.
+fn settle(last: u128, action: fn(NodeId) -> Moderation, reason: String) -> anyhow::Result<()> {
+    let id = match store::held_id(last)? {
+        Some(id) => id,
+        None => anyhow::bail!("nothing held as {}", last),
+    };
+    match store::moderate_offline(action(id), reason)? {
+        Response::Err(e) => anyhow::bail!("{}", e),
+        _ => println!("done."),
+    }
+    Ok(())
+}
 
+async fn accept(listener: TcpListener, acceptor: Option<TlsAcceptor>) -> anyhow::Result<()> {
     loop {
         let (socket, address) = listener.accept().await?;
         log::info!("Link from {} established.", address);
+        let acceptor = acceptor.clone();
         tokio::spawn(async move {
-            if let Err(e) = handle(socket).await {
+            let res = match acceptor {
+                Some(acceptor) => match acceptor.accept(socket).await {
+                    Ok(stream) => handle(stream, address.ip()).await,
+                    Err(e) => Err(e.into()),
+                },
+                None => handle(socket, address.ip()).await,
+            };
+            if let Err(e) = res {
                 log::warn!("link from {} failed: {}.", address, e);
             } else {
                 log::info!("link from {} ended.", address);
@@ -85,3 +466,174 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         });
     }
 }
+
+/// reload on SIGHUP, what needs restart is kept.
+async fn reload(opt: Opt) -> anyhow::Result<()> {
+    let mut hangup = signal(SignalKind::hangup())?;
+    while hangup.recv().await.is_some() {
+        match Config::load(opt.config.as_deref(), &opt.overrides) {
+            Ok(new) => {
+                let config = config::current().reload(new);
+                log::set_max_level(config.log_level()?);
+                config::set(config);
+                stamp::adjust();
+                match filter::reload() {
+                    Ok(count) => log::info!("[filter] {} rules loaded.", count),
+                    Err(e) => log::warn!("[filter] reload failed, keep the old rules: {}.", e),
+                }
+                log::info!("[config] reloaded.");
+            }
+            Err(e) => log::warn!("[config] reload failed, keep the old one: {}.", e),
+        }
+    }
+    Ok(())
+}
+
+async fn serve(opt: Opt) -> anyhow::Result<()> {
+    let config = config::current();
+    log::info!("[filter] {} rules loaded.", filter::reload()?);
+    // tls is enabled when both cert and key are given.
+    let acceptor = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => Some(tls::acceptor(cert, key)?),
+        _ => {
+            log::warn!("tls cert or key not set, tls disabled.");
+            None
+        }
+    };
+
+    let mut listeners = Vec::new();
+    for address in config.listen.iter() {
+        let listener = TcpListener::bind(address).await?;
+        log::info!("listen on {}.", address);
+        listeners.push(tokio::spawn(accept(listener, acceptor.clone())));
+    }
+    stamp::adjust();
+    tokio::spawn(stamp::keep_adjusting());
+    tokio::spawn(store::purge());
+    tokio::spawn(reload(opt));
+    for listener in listeners {
+        listener.await??;
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut opt = Opt::from_args();
+    let config = Config::load(opt.config.as_deref(), &opt.overrides)?;
+    // logs
+    log_panics::init();
+    config::init_log(&config)?;
+    config::set(config);
+
+    match opt.command.take().unwrap_or(Command::Serve) {
+        Command::Serve => {
+            store::check_schema()?;
+            store::prepare()?;
+            serve(opt).await?
+        }
+        Command::Migrate { dry_run } => {
+            let report = store::migrate(dry_run)?;
+            print!("{}", report);
+            if report.steps.is_empty() {
+                println!("database is up to date.");
+            } else if dry_run {
+                println!("dry run, nothing changed.");
+            } else {
+                println!("migrated.");
+            }
+        }
+        Command::Export { output } => {
+            store::check_schema()?;
+            let count = match output {
+                Some(path) => store::export(BufWriter::new(File::create(path)?))?,
+                None => store::export(io::stdout().lock())?,
+            };
+            eprintln!("{} nodes exported.", count);
+        }
+        Command::Import { input } => {
+            store::check_schema()?;
+            let (imported, skipped) = match input {
+                Some(path) => store::import(BufReader::new(File::open(path)?))?,
+                None => store::import(io::stdin().lock())?,
+            };
+            println!("{} nodes imported, {} already here skipped.", imported, skipped);
+        }
+        Command::Stats { top } => {
+            store::check_schema()?;
+            print!("{}", store::stats(top)?);
+        }
+        Command::Compact => {
+            let (before, after) = store::compact()?;
+            println!("compacted, {} KiB to {} KiB.", before / 1024, after / 1024);
+        }
+        Command::Key { name } => {
+            let mut pass = String::new();
+            io::stdin().lock().read_line(&mut pass)?;
+            let pass = pass.trim_end_matches(&['\r', '\n'][..]).to_owned();
+            println!("{}", store::key_of(Author::new(name, pass)));
+        }
+        Command::ModLog { last } => {
+            store::check_schema()?;
+            for record in store::mod_log(last)? {
+                println!("{}", record);
+            }
+        }
+        Command::Held => {
+            store::check_schema()?;
+            for (node, reason) in store::held()? {
+                let last = node.last_id()?;
+                println!("{} (depth {}) {}: {}", last, node.depth(), node.title, reason);
+            }
+        }
+        Command::Approve { id, reason } => {
+            store::check_schema()?;
+            store::prepare()?;
+            settle(id, Moderation::Approve, reason)?;
+        }
+        Command::Discard { id, reason } => {
+            store::check_schema()?;
+            store::prepare()?;
+            settle(id, Moderation::Discard, reason)?;
+        }
+        Command::Fsck { dry_run } => {
+            store::check_schema()?;
+            let report = store::fsck(dry_run)?;
+            print!("{}", report);
+            if report.is_clean() {
+                println!("database is clean.");
+            } else if dry_run {
+                println!("dry run, nothing repaired.");
+            } else {
+                println!("repaired.");
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Peer, PAGE_LIMIT};
+    use std::net::{IpAddr, Ipv4Addr};
+    use terminus_types::action::{Action, Envelope, ListTarget};
+
+    #[test]
+    fn page_limit() {
+        let address = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        // v0 list of root, all of it
+        let frame = bincode::serialize(&(1u32, 0u32)).unwrap();
+        let request = Peer::legacy(address).read_request(&frame);
+        assert!(matches!(request, Ok((0, Action::List { limit: u32::MAX, .. }))));
+        let mut peer = Peer::legacy(address);
+        peer.envelope = true;
+        let action = Action::List {
+            target: ListTarget::Root,
+            limit: u32::MAX,
+            cursor: None,
+        };
+        let frame = bincode::serialize(&Envelope::Request { id: 1, action }).unwrap();
+        let request = peer.read_request(&frame);
+        assert!(matches!(request, Ok((1, Action::List { limit: PAGE_LIMIT, .. }))));
+    }
+}
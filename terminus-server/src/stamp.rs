@@ -0,0 +1,240 @@
+//! proof of work posts need, harder when the board is busy.
+
+use crate::config::{self, ProofOfWork};
+use once_cell::sync::Lazy;
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use terminus_types::{
+    stamp::{self, Difficulty, Salt},
+    Draft, Error,
+};
+use tokio::sync::watch;
+
+/// a lowered difficulty is still taken for a while, for stamps made before.
+const GRACE: Duration = Duration::from_secs(30);
+/// how long a salt is given out, stamps made with it are taken one more while after.
+const SALT_LIFE: Duration = Duration::from_secs(10 * 60);
+/// salts stamps are taken with, the newest and the one before.
+const SALTS: usize = 2;
+
+/// a salt given out, and work of stamps taken with it.
+struct Bucket {
+    salt: Salt,
+    since: Instant,
+    /// they can not be used again, forgotten with the salt.
+    seen: HashSet<[u8; 32]>,
+}
+
+impl Bucket {
+    fn new(now: Instant) -> Self {
+        Self {
+            salt: rand::random(),
+            since: now,
+            seen: HashSet::new(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Load {
+    /// times of posts in the window.
+    posts: VecDeque<Instant>,
+    /// salts stamps can be made with, newest last.
+    buckets: VecDeque<Bucket>,
+    /// difficulty before the last change, and when it changed.
+    previous: Option<(u32, Instant)>,
+}
+
+impl Load {
+    fn prune(&mut self, window: Duration, now: Instant) {
+        while let Some(front) = self.posts.front() {
+            if now.saturating_duration_since(*front) <= window {
+                break;
+            }
+            self.posts.pop_front();
+        }
+        if self
+            .buckets
+            .back()
+            .is_none_or(|bucket| now.saturating_duration_since(bucket.since) >= SALT_LIFE)
+        {
+            self.buckets.push_back(Bucket::new(now));
+        }
+        while self.buckets.len() > SALTS {
+            self.buckets.pop_front();
+        }
+    }
+
+    /// salt given out now.
+    fn salt(&self) -> Salt {
+        self.buckets.back().map(|bucket| bucket.salt).unwrap_or_default()
+    }
+
+    /// least difficulty a stamp can have now.
+    fn needed(&self, current: u32, now: Instant) -> u32 {
+        match self.previous {
+            Some((previous, at)) if now.saturating_duration_since(at) < GRACE => previous.min(current),
+            _ => current,
+        }
+    }
+}
+
+/// difficulty of load, zero if turned off.
+fn difficulty_of(config: &ProofOfWork, posts: usize) -> u32 {
+    if config.difficulty == 0 {
+        return 0;
+    }
+    let extra = match config.posts_per_bit {
+        0 => 0,
+        per_bit => (posts / per_bit as usize) as u32,
+    };
+    config
+        .difficulty
+        .saturating_add(extra)
+        .min(config.max_difficulty.max(config.difficulty))
+}
+
+static LOAD: Lazy<Mutex<Load>> = Lazy::new(Default::default);
+
+type Channel = (watch::Sender<Difficulty>, watch::Receiver<Difficulty>);
+
+/// difficulty in effect and salt given out, links tell them to clients.
+static DIFFICULTY: Lazy<Channel> = Lazy::new(|| watch::channel(Difficulty::default()));
+
+pub(crate) fn watch() -> watch::Receiver<Difficulty> {
+    DIFFICULTY.1.clone()
+}
+
+/// work of the stamp if it is good enough, none if not needed.
+/// stamps made with a salt no longer kept are too old.
+/// the work is taken at once, so the same stamp on two links passes only one.
+pub(crate) fn check(draft: &Draft) -> Result<Option<[u8; 32]>, Error> {
+    let current = DIFFICULTY.1.borrow().bits;
+    let now = Instant::now();
+    let mut load = LOAD.lock().unwrap();
+    let difficulty = load.needed(current, now);
+    if difficulty == 0 {
+        return Ok(None);
+    }
+    let invalid = || Error::StampInvalid { difficulty: current };
+    // work with a salt it was not made with is no better than a try
+    let (bucket, work) = load
+        .buckets
+        .iter_mut()
+        .rev()
+        .find_map(|bucket| {
+            let work = draft.work(&bucket.salt)?;
+            Some((bucket, work)).filter(|_| stamp::zeros(&work) >= difficulty)
+        })
+        .ok_or_else(invalid)?;
+    if !bucket.seen.insert(work) {
+        return Err(invalid());
+    }
+    Ok(Some(work))
+}
+
+/// give back work taken by a post that failed, it can be sent again.
+pub(crate) fn release(work: Option<[u8; 32]>) {
+    if let Some(work) = work {
+        for bucket in LOAD.lock().unwrap().buckets.iter_mut() {
+            bucket.seen.remove(&work);
+        }
+    }
+}
+
+/// count a post for load.
+pub(crate) fn posted() {
+    LOAD.lock().unwrap().posts.push_back(Instant::now());
+    adjust();
+}
+
+/// forget what is out of window or too old, and tell if difficulty or salt changed.
+pub(crate) fn adjust() {
+    let config = config::current();
+    let pow = &config.proof_of_work;
+    let now = Instant::now();
+    let mut load = LOAD.lock().unwrap();
+    load.prune(Duration::from_secs(pow.window_seconds), now);
+    let bits = difficulty_of(pow, load.posts.len());
+    let salt = load.salt();
+    let current = *DIFFICULTY.1.borrow();
+    if bits != current.bits {
+        log::info!("[stamp] difficulty {} to {}.", current.bits, bits);
+        // nothing was stamped while turned off
+        load.previous = Some((current.bits, now)).filter(|_| current.bits > 0);
+    }
+    if bits != current.bits || salt != current.salt {
+        // a receiver is kept here, never fails
+        DIFFICULTY.0.send(Difficulty { bits, salt }).ok();
+    }
+}
+
+/// let difficulty fall as posts leave the window.
+pub(crate) async fn keep_adjusting() {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        adjust();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{difficulty_of, Load, ProofOfWork, GRACE, SALTS, SALT_LIFE};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn difficulty_by_load() {
+        let mut pow = ProofOfWork {
+            difficulty: 10,
+            max_difficulty: 12,
+            window_seconds: 60,
+            posts_per_bit: 30,
+        };
+        assert_eq!(10, difficulty_of(&pow, 29));
+        assert_eq!(11, difficulty_of(&pow, 30));
+        assert_eq!(12, difficulty_of(&pow, 1000));
+        pow.posts_per_bit = 0;
+        assert_eq!(10, difficulty_of(&pow, 1000));
+        pow.difficulty = 0;
+        assert_eq!(0, difficulty_of(&pow, 1000));
+    }
+
+    #[test]
+    fn grace() {
+        let start = Instant::now();
+        let mut load = Load::default();
+        load.posts.push_back(start);
+        load.previous = Some((8, start));
+        assert_eq!(8, load.needed(12, start));
+        assert_eq!(4, load.needed(4, start));
+        assert_eq!(12, load.needed(12, start + GRACE));
+        load.prune(Duration::from_secs(60), start + Duration::from_secs(61));
+        assert!(load.posts.is_empty());
+    }
+
+    #[test]
+    fn salts() {
+        let start = Instant::now();
+        let window = Duration::from_secs(60);
+        let mut load = Load::default();
+        load.prune(window, start);
+        let first = load.salt();
+        load.buckets.back_mut().unwrap().seen.insert([0; 32]);
+        load.prune(window, start + SALT_LIFE / 2);
+        assert_eq!(first, load.salt());
+        load.prune(window, start + SALT_LIFE);
+        assert_ne!(first, load.salt());
+        // the one before is still taken
+        assert_eq!(first, load.buckets[0].salt);
+        load.prune(window, start + SALT_LIFE * 2);
+        assert_eq!(SALTS, load.buckets.len());
+        assert!(load
+            .buckets
+            .iter()
+            .all(|bucket| bucket.salt != first && bucket.seen.is_empty()));
+    }
+}
@@ -0,0 +1,445 @@
+//! board maintenance, run with the server stopped.
+
+use super::{
+    aggregate, archive, assemble_node, disperse_node, history,
+    moderate::{self, Held, Record},
+    order,
+    tombstone::{self, Buried},
+    NodeBody, CONTENT_TREE, DB, ROOT_LIST,
+};
+use crate::config;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sled::{
+    transaction::{TransactionError, Transactional},
+    Batch, IVec, Tree,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs,
+    io::{BufRead, Write},
+};
+use terminus_types::{decode_id, Author, Node, Revision, ID_LEN};
+
+/// a line of export, nodes first and what else the board keeps after.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Line {
+    Node(Box<NodeLine>),
+    /// banned tripcode or address, and log id of the ban.
+    Ban {
+        ban: String,
+        log: Vec<u8>,
+    },
+    /// what a tombstone was, until retention is over.
+    Tombstone {
+        tombstone: Vec<u8>,
+        buried: Buried,
+    },
+    /// node id and when it was replaced, and what it said.
+    Revision {
+        revision: Vec<u8>,
+        was: Revision,
+    },
+    /// log id and one moderation done.
+    Record {
+        record: Vec<u8>,
+        done: Record,
+    },
+    /// node id and the address it was posted from.
+    Source {
+        source: Vec<u8>,
+        address: String,
+    },
+    /// node id and a post or edit waiting for a moderator.
+    Held {
+        held: Vec<u8>,
+        waiting: Held,
+    },
+    /// filter rule name and times it matched.
+    Hits {
+        rule: String,
+        hits: u64,
+    },
+}
+
+/// a node, with what the board keeps about its thread if top level.
+#[derive(Serialize, Deserialize)]
+struct NodeLine {
+    #[serde(flatten)]
+    node: Node,
+    /// when the thread was archived.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    archived: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    locked: bool,
+}
+
+fn write_line<W: Write>(out: &mut W, line: &Line) -> anyhow::Result<()> {
+    serde_json::to_writer(&mut *out, line)?;
+    out.write_all(b"\n")?;
+    Ok(())
+}
+
+/// every node as a json line, in id order so parents go first, then bans, tombstones,
+/// revisions, the moderation log, sources, held posts and filter hits.
+/// threads keep if they are archived or locked. gives nodes exported.
+pub(crate) fn export<W: Write>(mut out: W) -> anyhow::Result<usize> {
+    let archive = archive::tree()?;
+    let locks = moderate::locks()?;
+    let mut count = 0;
+    for item in DB.open_tree(CONTENT_TREE)?.iter() {
+        let (id, body) = item?;
+        let (archived, locked) = if id.len() == ID_LEN {
+            let archived = match archive.get(&id)? {
+                Some(at) => Some(bincode::deserialize(&at)?),
+                None => None,
+            };
+            (archived, locks.contains_key(&id)?)
+        } else {
+            (None, false)
+        };
+        let node = assemble_node(&id, &body)?;
+        write_line(&mut out, &Line::Node(Box::new(NodeLine { node, archived, locked })))?;
+        count += 1;
+    }
+    for item in moderate::bans()?.iter() {
+        let (ban, log) = item?;
+        let ban = String::from_utf8(ban.to_vec())?;
+        write_line(&mut out, &Line::Ban { ban, log: log.to_vec() })?;
+    }
+    for item in tombstone::tree()?.iter() {
+        let (id, buried) = item?;
+        let buried = bincode::deserialize(&buried)?;
+        write_line(
+            &mut out,
+            &Line::Tombstone {
+                tombstone: id.to_vec(),
+                buried,
+            },
+        )?;
+    }
+    for item in history::tree()?.iter() {
+        let (key, revision) = item?;
+        let was = bincode::deserialize(&revision)?;
+        write_line(
+            &mut out,
+            &Line::Revision {
+                revision: key.to_vec(),
+                was,
+            },
+        )?;
+    }
+    for item in moderate::log_tree()?.iter() {
+        let (log_id, record) = item?;
+        let done = bincode::deserialize(&record)?;
+        write_line(
+            &mut out,
+            &Line::Record {
+                record: log_id.to_vec(),
+                done,
+            },
+        )?;
+    }
+    for item in moderate::sources()?.iter() {
+        let (id, address) = item?;
+        let address = String::from_utf8(address.to_vec())?;
+        write_line(
+            &mut out,
+            &Line::Source {
+                source: id.to_vec(),
+                address,
+            },
+        )?;
+    }
+    for item in moderate::held_tree()?.iter() {
+        let (id, held) = item?;
+        let waiting = bincode::deserialize(&held)?;
+        write_line(
+            &mut out,
+            &Line::Held {
+                held: id.to_vec(),
+                waiting,
+            },
+        )?;
+    }
+    for (rule, hits) in moderate::filter_hits()? {
+        write_line(&mut out, &Line::Hits { rule, hits })?;
+    }
+    out.flush()?;
+    Ok(count)
+}
+
+/// entries of a tree an import adds, those already here are left as they are.
+struct Adds {
+    tree: Tree,
+    batch: Batch,
+}
+
+impl Adds {
+    fn new(tree: Tree) -> Self {
+        Self {
+            tree,
+            batch: Batch::default(),
+        }
+    }
+
+    fn add<V: Into<IVec>>(&mut self, key: &[u8], value: V) -> sled::Result<()> {
+        if !self.tree.contains_key(key)? {
+            self.batch.insert(key, value);
+        }
+        Ok(())
+    }
+}
+
+/// restore an export, keeping ids. nodes and entries already here are skipped, bans are taken over.
+/// archived threads go back to archive, not root. all of it goes in one transaction.
+/// gives nodes imported and skipped.
+pub(crate) fn import<R: BufRead>(input: R) -> anyhow::Result<(usize, usize)> {
+    let content = DB.open_tree(CONTENT_TREE)?;
+    let mut content_batch = Batch::default();
+    let mut root_batch = Batch::default();
+    let mut order_batch = Batch::default();
+    let mut archive_batch = Batch::default();
+    let mut archive_order_batch = Batch::default();
+    let mut locks_batch = Batch::default();
+    let mut bans_batch = Batch::default();
+    let mut deleted = Adds::new(tombstone::tree()?);
+    let mut history = Adds::new(history::tree()?);
+    let mut log = Adds::new(moderate::log_tree()?);
+    let mut sources = Adds::new(moderate::sources()?);
+    let mut held = Adds::new(moderate::held_tree()?);
+    let mut hits = Adds::new(moderate::hits_tree()?);
+    let (mut imported, mut skipped) = (0, 0);
+    // nodes alive imported under each thread touched
+    let mut threads: BTreeMap<Vec<u8>, Vec<(Vec<u8>, Author)>> = BTreeMap::new();
+    for (ind, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line: Line = serde_json::from_str(&line).map_err(|e| anyhow::anyhow!("line {}: {}", ind + 1, e))?;
+        let NodeLine { node, archived, locked } = match line {
+            Line::Node(node) => *node,
+            Line::Ban { ban, log } => {
+                bans_batch.insert(ban.as_bytes(), log);
+                continue;
+            }
+            Line::Tombstone { tombstone, buried } => {
+                deleted.add(&tombstone, bincode::serialize(&buried)?)?;
+                continue;
+            }
+            Line::Revision { revision, was } => {
+                history.add(&revision, bincode::serialize(&was)?)?;
+                continue;
+            }
+            Line::Record { record, done } => {
+                log.add(&record, bincode::serialize(&done)?)?;
+                continue;
+            }
+            Line::Source { source, address } => {
+                sources.add(&source, address.as_bytes())?;
+                continue;
+            }
+            Line::Held { held: id, waiting } => {
+                held.add(&id, bincode::serialize(&waiting)?)?;
+                continue;
+            }
+            Line::Hits { rule, hits: times } => {
+                hits.add(rule.as_bytes(), &times.to_be_bytes())?;
+                continue;
+            }
+        };
+        if node.id.is_empty() || !node.id.len().is_multiple_of(ID_LEN) {
+            anyhow::bail!("line {}: node id not valid", ind + 1);
+        }
+        if content.contains_key(&node.id)? {
+            skipped += 1;
+            continue;
+        }
+        let is_top_level = node.is_top_level();
+        let alive = threads.entry(node.id[..ID_LEN].to_vec()).or_default();
+        if !node.deleted {
+            alive.push((node.id.clone(), node.author.clone()));
+        }
+        let (id, body) = disperse_node(node)?;
+        if is_top_level {
+            if let Some(at) = archived {
+                archive_order_batch.insert(order::archive_key(&id, &body), IVec::default());
+                archive_batch.insert(id.clone(), bincode::serialize(&at)?);
+            } else {
+                order_batch.insert(order::root_key(&id, &body), IVec::default());
+                root_batch.insert(id.clone(), body.clone());
+            }
+            if locked {
+                locks_batch.insert(id.clone(), IVec::default());
+            }
+        }
+        content_batch.insert(id, body);
+        imported += 1;
+    }
+    let trees = (
+        &content,
+        &DB.open_tree(ROOT_LIST)?,
+        &order::root_tree()?,
+        &archive::tree()?,
+        &order::archive_tree()?,
+        &moderate::locks()?,
+        &moderate::bans()?,
+        &aggregate::tree()?,
+        &deleted.tree,
+        &history.tree,
+        &log.tree,
+        &sources.tree,
+        &held.tree,
+        &hits.tree,
+    );
+    let res = trees.transaction(
+        |(
+            content,
+            root_list,
+            root_order,
+            archive,
+            archive_order,
+            locks,
+            bans,
+            aggregates,
+            deleted_tree,
+            history_tree,
+            log_tree,
+            sources_tree,
+            held_tree,
+            hits_tree,
+        )| {
+            content.apply_batch(&content_batch)?;
+            root_list.apply_batch(&root_batch)?;
+            root_order.apply_batch(&order_batch)?;
+            archive.apply_batch(&archive_batch)?;
+            archive_order.apply_batch(&archive_order_batch)?;
+            locks.apply_batch(&locks_batch)?;
+            bans.apply_batch(&bans_batch)?;
+            deleted_tree.apply_batch(&deleted.batch)?;
+            history_tree.apply_batch(&history.batch)?;
+            log_tree.apply_batch(&log.batch)?;
+            sources_tree.apply_batch(&sources.batch)?;
+            held_tree.apply_batch(&held.batch)?;
+            hits_tree.apply_batch(&hits.batch)?;
+            for (top, nodes) in threads.iter() {
+                let alive: Vec<_> = nodes.iter().map(|(id, author)| (id.as_slice(), author)).collect();
+                aggregate::add(aggregates, top, &alive)?;
+            }
+            Ok(())
+        },
+    );
+    match res {
+        Ok(()) => {}
+        Err(TransactionError::Abort(e)) => return Err(e.into()),
+        Err(TransactionError::Storage(e)) => return Err(e.into()),
+    }
+    DB.flush()?;
+    Ok((imported, skipped))
+}
+
+pub(crate) struct Stats {
+    pub threads: usize,
+    pub archived: usize,
+    /// replies alive, tombstones left out.
+    pub replies: usize,
+    /// bytes.
+    pub size: u64,
+    /// id, title and replies of threads with most replies.
+    pub busiest: Vec<(u128, String, usize)>,
+    /// filter rules and times they matched, by name.
+    pub filter_hits: Vec<(String, u64)>,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "threads: {}", self.threads)?;
+        writeln!(f, "archived threads: {}", self.archived)?;
+        writeln!(f, "replies: {}", self.replies)?;
+        writeln!(f, "database size: {} KiB", self.size / 1024)?;
+        writeln!(f, "busiest threads:")?;
+        for (id, title, replies) in self.busiest.iter() {
+            writeln!(f, "  {:>6} {} {}", replies, id, title)?;
+        }
+        writeln!(f, "filter hits:")?;
+        for (name, hits) in self.filter_hits.iter() {
+            writeln!(f, "  {:>6} {}", hits, name)?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn stats(top: usize) -> anyhow::Result<Stats> {
+    let content = DB.open_tree(CONTENT_TREE)?;
+    let mut replies: HashMap<Vec<u8>, usize> = HashMap::new();
+    let mut total = 0;
+    for item in content.iter() {
+        let (key, body) = item?;
+        // tombstones are not replies any more
+        if key.len() > ID_LEN && !NodeBody::decode(&body)?.deleted {
+            *replies.entry(key[..ID_LEN].to_vec()).or_default() += 1;
+            total += 1;
+        }
+    }
+    let mut busiest: Vec<_> = replies.into_iter().collect();
+    busiest.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    busiest.truncate(top);
+    let busiest = busiest
+        .into_iter()
+        .map(|(id, count)| {
+            let title = match content.get(&id)? {
+                Some(body) => assemble_node(&id, &body)?.title,
+                None => "[gone]".to_owned(),
+            };
+            Ok((decode_id(&id)?, title, count))
+        })
+        .collect::<anyhow::Result<_>>()?;
+    Ok(Stats {
+        threads: DB.open_tree(ROOT_LIST)?.len(),
+        archived: archive::len()?,
+        replies: total,
+        size: DB.size_on_disk()?,
+        busiest,
+        filter_hits: moderate::filter_hits()?,
+    })
+}
+
+/// copy everything into a fresh database, swap it in and open it again to check it.
+/// the database is opened here alone, so it refuses while a server or anything else has it.
+/// gives size before and after.
+pub(crate) fn compact() -> anyhow::Result<(u64, u64)> {
+    let path = config::current().database.clone();
+    if !path.exists() {
+        anyhow::bail!("no database at {}", path.display());
+    }
+    let db = sled::open(&path).map_err(|e| anyhow::anyhow!("database is in use, stop the server first: {}", e))?;
+    let before = db.size_on_disk()?;
+    let checksum = db.checksum()?;
+    let fresh_path = path.with_extension("compact");
+    let old_path = path.with_extension("old");
+    // left by a failed compact
+    if fresh_path.exists() {
+        fs::remove_dir_all(&fresh_path)?;
+    }
+    let after = {
+        let fresh = sled::open(&fresh_path)?;
+        fresh.import(db.export());
+        fresh.flush()?;
+        fresh.size_on_disk()?
+    };
+    // let go of it before the swap
+    drop(db);
+    fs::rename(&path, &old_path)?;
+    fs::rename(&fresh_path, &path)?;
+    let db = sled::open(&path)?;
+    if db.checksum()? != checksum {
+        drop(db);
+        fs::rename(&path, &fresh_path)?;
+        fs::rename(&old_path, &path)?;
+        anyhow::bail!("compacted database differs, the old one is kept");
+    }
+    fs::remove_dir_all(&old_path)?;
+    Ok((before, after))
+}
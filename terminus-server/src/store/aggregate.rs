@@ -0,0 +1,293 @@
+//! what is under each thread, counted in the same transaction as posts and deletes.
+
+use super::{abort, archive, read_body, NodeBody, CONTENT_TREE, DB, ROOT_LIST};
+use serde::{Deserialize, Serialize};
+use sled::{
+    transaction::{ConflictableTransactionResult, TransactionalTree},
+    IVec, Tree,
+};
+use std::collections::BTreeMap;
+use terminus_types::{
+    action::{Envelope, Response},
+    Author, Error, Node, NodeId, ThreadStats, ID_LEN,
+};
+use tokio::sync::mpsc::UnboundedSender;
+
+/// thread id to its aggregate, one for every thread in content.
+const AGGREGATES: &str = "thread_stats";
+
+#[derive(Serialize, Deserialize, Default)]
+pub(super) struct Aggregate {
+    /// replies alive, tombstones are not counted.
+    pub replies: u64,
+    /// masked passes of nodes alive, to how many nodes each has.
+    tripcodes: BTreeMap<String, u64>,
+    /// newest reply alive, and who posted it.
+    last: Option<(NodeId, Author)>,
+}
+
+fn last_layer(id: &[u8]) -> &[u8] {
+    &id[id.len() - ID_LEN..]
+}
+
+impl Aggregate {
+    /// count a node alive, just posted or restored.
+    fn add(&mut self, id: &[u8], author: &Author) {
+        *self.tripcodes.entry(author.pass.get_pass().to_owned()).or_default() += 1;
+        if id.len() > ID_LEN {
+            self.replies += 1;
+            // ids of the last layer are in post order
+            if self
+                .last
+                .as_ref()
+                .is_none_or(|(last, _)| last_layer(last) < last_layer(id))
+            {
+                self.last = Some((id.to_owned(), author.clone()));
+            }
+        }
+    }
+
+    /// a node no longer alive, gives if it was the newest reply.
+    fn remove(&mut self, id: &[u8], author: &Author) -> bool {
+        let pass = author.pass.get_pass();
+        if let Some(count) = self.tripcodes.get_mut(pass) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                self.tripcodes.remove(pass);
+            }
+        }
+        if id.len() == ID_LEN {
+            return false;
+        }
+        self.replies = self.replies.saturating_sub(1);
+        if self.last.as_ref().is_some_and(|(last, _)| last == id) {
+            self.last = None;
+            return true;
+        }
+        false
+    }
+
+    fn stats(&self) -> ThreadStats {
+        ThreadStats {
+            replies: self.replies,
+            posters: self.tripcodes.len() as u64,
+            last_author: self.last.as_ref().map(|(_, author)| author.clone()),
+        }
+    }
+}
+
+fn encode(aggregate: &Aggregate) -> Vec<u8> {
+    bincode::serialize(aggregate).expect("should always serialize success")
+}
+
+/// aggregate of thread as the transaction sees it.
+pub(super) fn read(aggregates: &TransactionalTree, top: &[u8]) -> ConflictableTransactionResult<Aggregate, Error> {
+    match aggregates.get(top)? {
+        Some(bin) => bincode::deserialize(&bin).or_else(|e| {
+            log::error!("thread stats broken: {}.", e);
+            abort(Error::Internal)
+        }),
+        None => Ok(Aggregate::default()),
+    }
+}
+
+fn write(
+    aggregates: &TransactionalTree,
+    top: &[u8],
+    aggregate: &Aggregate,
+) -> ConflictableTransactionResult<(), Error> {
+    aggregates.insert(top, encode(aggregate))?;
+    Ok(())
+}
+
+/// count nodes alive under top, posted or restored.
+pub(super) fn add(
+    aggregates: &TransactionalTree,
+    top: &[u8],
+    alive: &[(&[u8], &Author)],
+) -> ConflictableTransactionResult<(), Error> {
+    let mut aggregate = read(aggregates, top)?;
+    for (id, author) in alive {
+        aggregate.add(id, author);
+    }
+    write(aggregates, top, &aggregate)
+}
+
+/// replies of thread newest first, taken before a transaction that may remove the newest.
+/// those posted after are newer than any removed, so never needed.
+pub(super) fn replies_of(content: &Tree, top: &[u8]) -> sled::Result<Vec<IVec>> {
+    let mut replies = Vec::new();
+    for key in content.scan_prefix(&top[..ID_LEN]).keys() {
+        let key = key?;
+        if key.len() > ID_LEN {
+            replies.push(key);
+        }
+    }
+    replies.sort_unstable_by(|a, b| last_layer(b).cmp(last_layer(a)));
+    Ok(replies)
+}
+
+/// take nodes gone under top off its count, after they are buried in content.
+/// if the newest reply went, the next one alive in replies takes its place.
+pub(super) fn remove(
+    aggregates: &TransactionalTree,
+    content: &TransactionalTree,
+    top: &[u8],
+    gone: &[(&[u8], &Author)],
+    replies: &[IVec],
+) -> ConflictableTransactionResult<(), Error> {
+    let mut aggregate = read(aggregates, top)?;
+    let mut newest_gone = false;
+    for (id, author) in gone {
+        newest_gone |= aggregate.remove(id, author);
+    }
+    if newest_gone {
+        for key in replies {
+            match content.get(key)? {
+                Some(body) => {
+                    let body = read_body(&body)?;
+                    if !body.deleted {
+                        aggregate.last = Some((key.to_vec(), body.author));
+                        break;
+                    }
+                }
+                None => continue,
+            }
+        }
+    }
+    write(aggregates, top, &aggregate)
+}
+
+/// count thread whole from content.
+fn count(content: &Tree, top: &[u8]) -> anyhow::Result<Aggregate> {
+    let mut aggregate = Aggregate::default();
+    for item in content.scan_prefix(top) {
+        let (id, body) = item?;
+        let body = NodeBody::decode(&body)?;
+        if !body.deleted {
+            aggregate.add(&id, &body.author);
+        }
+    }
+    Ok(aggregate)
+}
+
+pub(super) fn tree() -> sled::Result<Tree> {
+    DB.open_tree(AGGREGATES)
+}
+
+/// threads not counted yet, like those from before stats.
+pub(super) fn uncounted() -> anyhow::Result<usize> {
+    let content = DB.open_tree(CONTENT_TREE)?;
+    let tree = DB.open_tree(AGGREGATES)?;
+    let mut uncounted = 0;
+    for top in DB
+        .open_tree(ROOT_LIST)?
+        .iter()
+        .keys()
+        .chain(archive::tree()?.iter().keys())
+    {
+        let top = top?;
+        if tree.contains_key(&top)? || !content.contains_key(&top)? {
+            continue;
+        }
+        uncounted += 1;
+    }
+    Ok(uncounted)
+}
+
+/// threads counted again whose stats drifted, and stats of threads gone.
+/// fixed unless dry run.
+pub(super) fn check(threads: &[IVec], dry_run: bool) -> anyhow::Result<Vec<IVec>> {
+    let content = DB.open_tree(CONTENT_TREE)?;
+    let tree = DB.open_tree(AGGREGATES)?;
+    let mut stale = Vec::new();
+    for top in threads {
+        let counted = match count(&content, top) {
+            Ok(counted) => encode(&counted),
+            Err(e) => {
+                log::warn!("[fsck] thread {:?} can not be counted, skipped: {}.", top, e);
+                continue;
+            }
+        };
+        if tree.get(top)?.as_deref() != Some(counted.as_slice()) {
+            stale.push(top.clone());
+            if !dry_run {
+                tree.insert(top, counted)?;
+            }
+        }
+    }
+    for top in tree.iter().keys() {
+        let top = top?;
+        if threads.binary_search(&top).is_err() {
+            stale.push(top.clone());
+            if !dry_run {
+                tree.remove(&top)?;
+            }
+        }
+    }
+    Ok(stale)
+}
+
+/// stats of threads listed, read only.
+pub(crate) fn stats_of(nodes: &[Node]) -> anyhow::Result<Vec<(NodeId, ThreadStats)>> {
+    let tree = DB.open_tree(AGGREGATES)?;
+    let mut res = Vec::new();
+    for node in nodes.iter().filter(|node| node.is_top_level()) {
+        if let Some(aggregate) = tree.get(&node.id)? {
+            let aggregate: Aggregate = bincode::deserialize(&aggregate)?;
+            res.push((node.id.clone(), aggregate.stats()));
+        }
+    }
+    Ok(res)
+}
+
+/// push stats as threads change.
+pub(super) async fn forward(s: UnboundedSender<Envelope>) -> anyhow::Result<()> {
+    let mut inbox = DB.open_tree(AGGREGATES)?.watch_prefix([]);
+    while let Some(event) = (&mut inbox).await {
+        // gone with their thread
+        if let sled::Event::Insert { key, value } = event {
+            let aggregate: Aggregate = bincode::deserialize(&value)?;
+            let resp = Response::Stats(vec![(key.to_vec(), aggregate.stats())]);
+            s.send(Envelope::Event(resp))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Aggregate;
+    use terminus_types::{encode_id, Author};
+
+    #[test]
+    fn posters_and_last_author() {
+        let author = |name: &str| {
+            let mut author = Author::new(name.to_owned(), name.to_owned());
+            author.mask();
+            author
+        };
+        let id = |layers: &[u128]| layers.iter().flat_map(|layer| encode_id(*layer)).collect::<Vec<u8>>();
+        let mut aggregate = Aggregate::default();
+        aggregate.add(&id(&[1]), &author("op"));
+        aggregate.add(&id(&[1, 2]), &author("a"));
+        aggregate.add(&id(&[1, 3]), &author("op"));
+        aggregate.add(&id(&[1, 2, 4]), &author("b"));
+        let stats = aggregate.stats();
+        assert_eq!((3, 3), (stats.replies, stats.posters));
+        assert_eq!(Some("b".to_owned()), stats.last_author.map(|author| author.name));
+        // not the newest, last author stays
+        assert!(!aggregate.remove(&id(&[1, 2]), &author("a")));
+        assert!(aggregate.remove(&id(&[1, 2, 4]), &author("b")));
+        let stats = aggregate.stats();
+        assert_eq!((1, 1), (stats.replies, stats.posters));
+        assert!(stats.last_author.is_none());
+        // restored out of order, newest wins
+        aggregate.add(&id(&[1, 2, 4]), &author("b"));
+        aggregate.add(&id(&[1, 2]), &author("a"));
+        assert_eq!(
+            Some("b".to_owned()),
+            aggregate.stats().last_author.map(|author| author.name)
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//! threads moved off root when there are too many, read only and kept a while.
+
+use super::{
+    abort, aggregate, assemble_node, history, moderate, order, read_body, tombstone, NodeBody, CONTENT_TREE, DB,
+    ROOT_LIST,
+};
+use crate::config;
+use chrono::{DateTime, Utc};
+use sled::{
+    transaction::{TransactionError, Transactional},
+    IVec,
+};
+use terminus_types::{
+    action::{Cursor, ListTarget, Response},
+    decode_id, Error, ID_LEN,
+};
+
+/// thread id to when it was archived.
+const ARCHIVE: &str = "archive";
+
+/// if the thread of id is archived.
+pub(super) fn contains(id: &[u8]) -> anyhow::Result<bool> {
+    match id.get(..ID_LEN) {
+        Some(top) => Ok(DB.open_tree(ARCHIVE)?.contains_key(top)?),
+        None => Ok(false),
+    }
+}
+
+pub(super) fn tree() -> sled::Result<sled::Tree> {
+    DB.open_tree(ARCHIVE)
+}
+
+pub(super) fn len() -> anyhow::Result<usize> {
+    Ok(DB.open_tree(ARCHIVE)?.len())
+}
+
+/// most threads moved by one call, upkeep moves the rest.
+const MOVE_LIMIT: usize = 64;
+
+/// move least recently bumped threads off root, until no more than max live.
+/// sticky threads stay. gives how many are moved.
+pub(super) fn overflow() -> anyhow::Result<usize> {
+    let max = match config::current().threads.max_live {
+        Some(max) => max,
+        None => return Ok(0),
+    };
+    let root_list = DB.open_tree(ROOT_LIST)?;
+    let over = root_list.len().saturating_sub(max).min(MOVE_LIMIT);
+    if over == 0 {
+        return Ok(0);
+    }
+    let root_order = order::root_tree()?;
+    let keys = order::unstuck(&root_order)
+        .take(over)
+        .collect::<sled::Result<Vec<_>>>()?;
+    let archive = DB.open_tree(ARCHIVE)?;
+    let archive_order = order::archive_tree()?;
+    let now = bincode::serialize(&Utc::now())?;
+    let trees = (&root_list, &root_order, &archive, &archive_order);
+    let moved = trees.transaction(|(root_list, root_order, archive, archive_order)| {
+        let mut moved = Vec::new();
+        for key in keys.iter() {
+            let id = order::id_of(key);
+            let body = match root_list.get(id)? {
+                Some(body) => read_body(&body)?,
+                None => continue,
+            };
+            // bumped or stuck meanwhile, stays
+            if order::root_key(id, &body) != key.as_ref() {
+                continue;
+            }
+            root_list.remove(id)?;
+            root_order.remove(key.clone())?;
+            archive.insert(id, now.as_slice())?;
+            archive_order.insert(order::archive_key(id, &body), IVec::default())?;
+            moved.push(IVec::from(id));
+        }
+        Ok(moved)
+    })?;
+    for id in moved.iter() {
+        log::info!("[archive] thread {} archived.", decode_id(id)?);
+    }
+    Ok(moved.len())
+}
+
+/// delete threads archived longer than kept, gives how many nodes are removed.
+pub(super) fn expire() -> anyhow::Result<usize> {
+    let keep = match config::current().archive_keep() {
+        Some(keep) => keep,
+        None => return Ok(0),
+    };
+    let now = Utc::now();
+    let archive = DB.open_tree(ARCHIVE)?;
+    let archive_order = order::archive_tree()?;
+    let content = DB.open_tree(CONTENT_TREE)?;
+    let locks = moderate::locks()?;
+    let aggregates = aggregate::tree()?;
+    let history = history::tree()?;
+    let deleted = tombstone::tree()?;
+    let sources = moderate::sources()?;
+    let mut removed = 0;
+    for item in archive.iter() {
+        let (top, at) = item?;
+        if now - bincode::deserialize::<DateTime<Utc>>(&at)? <= keep {
+            continue;
+        }
+        // archived threads never change, what goes is taken before the transaction
+        let order_key = match content.get(&top)? {
+            Some(body) => Some(order::archive_key(&top, &NodeBody::decode(&body)?)),
+            None => None,
+        };
+        let nodes = content.scan_prefix(&top).keys().collect::<sled::Result<Vec<_>>>()?;
+        let revisions = history.scan_prefix(&top).keys().collect::<sled::Result<Vec<_>>>()?;
+        let trees = (
+            &content,
+            &archive,
+            &archive_order,
+            &locks,
+            &aggregates,
+            &history,
+            &deleted,
+            &sources,
+        );
+        let res = trees.transaction(
+            |(content, archive, archive_order, locks, aggregates, history, deleted, sources)| {
+                if archive.remove(&top)?.is_none() {
+                    return abort(Error::NodeNotExist);
+                }
+                if let Some(key) = order_key.as_ref() {
+                    archive_order.remove(key.as_slice())?;
+                }
+                for key in nodes.iter() {
+                    content.remove(key)?;
+                    sources.remove(key)?;
+                    deleted.remove(key)?;
+                }
+                for key in revisions.iter() {
+                    history.remove(key)?;
+                }
+                locks.remove(&top)?;
+                aggregates.remove(&top)?;
+                Ok(())
+            },
+        );
+        match res {
+            Ok(()) => removed += nodes.len(),
+            // expired meanwhile
+            Err(TransactionError::Abort(_)) => {}
+            Err(TransactionError::Storage(e)) => return Err(e.into()),
+        }
+    }
+    Ok(removed)
+}
+
+/// archive is listed by last reply, newest first. bumps are over, so order never changes.
+/// cursor is the order key of the last one.
+pub(crate) fn list_archive(limit: usize, cursor: Option<Cursor>) -> anyhow::Result<Response> {
+    let (keys, next) = match order::page(&order::archive_tree()?, order::ARCHIVE_KEY_LEN, limit, cursor)? {
+        Some(page) => page,
+        None => return Ok(Response::Err(Error::CursorInvalid)),
+    };
+    let content = DB.open_tree(CONTENT_TREE)?;
+    let mut res = Vec::new();
+    for key in keys {
+        let id = order::id_of(&key);
+        if let Some(body) = content.get(id)? {
+            res.push(assemble_node(id, &body)?);
+        }
+    }
+    Ok(Response::List {
+        target: ListTarget::Archive,
+        nodes: res,
+        next,
+    })
+}
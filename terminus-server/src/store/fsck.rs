@@ -0,0 +1,135 @@
+//! find and repair drift between content, root list, list order and thread stats.
+
+use super::{aggregate, archive, order, NodeBody, CONTENT_TREE, DB, ROOT_LIST};
+use sled::{Batch, IVec};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+};
+use terminus_types::{decode_id, ID_LEN};
+
+/// what fsck found, ids are shown by their last layer.
+#[derive(Default, Debug)]
+pub(crate) struct Report {
+    /// root entries whose thread is gone.
+    pub root_without_content: Vec<IVec>,
+    /// threads missing from root list.
+    pub content_without_root: Vec<IVec>,
+    /// archived threads still in root list.
+    pub archived_in_root: Vec<IVec>,
+    /// replies whose parent is gone.
+    pub orphans: Vec<IVec>,
+    /// threads whose root entry is out of date.
+    pub stale: Vec<IVec>,
+    /// threads whose list order is missing or out of date.
+    pub stale_order: Vec<IVec>,
+    /// threads whose stats drifted from what they have, or are gone.
+    pub stale_stats: Vec<IVec>,
+}
+
+impl Report {
+    pub(crate) fn is_clean(&self) -> bool {
+        self.root_without_content.is_empty()
+            && self.content_without_root.is_empty()
+            && self.archived_in_root.is_empty()
+            && self.orphans.is_empty()
+            && self.stale.is_empty()
+            && self.stale_order.is_empty()
+            && self.stale_stats.is_empty()
+    }
+}
+
+fn show_ids(f: &mut fmt::Formatter<'_>, label: &str, ids: &[IVec]) -> fmt::Result {
+    writeln!(f, "{}: {}", label, ids.len())?;
+    for id in ids {
+        match id.len().checked_sub(ID_LEN).and_then(|start| decode_id(&id[start..]).ok()) {
+            Some(last) => writeln!(f, "  {} (depth {})", last, id.len() / ID_LEN)?,
+            None => writeln!(f, "  {:?}", id)?,
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        show_ids(f, "root entries without content", &self.root_without_content)?;
+        show_ids(f, "content without root entry", &self.content_without_root)?;
+        show_ids(f, "archived threads in root list", &self.archived_in_root)?;
+        show_ids(f, "orphaned replies", &self.orphans)?;
+        show_ids(f, "stale threads", &self.stale)?;
+        show_ids(f, "stale list order", &self.stale_order)?;
+        show_ids(f, "stale thread stats", &self.stale_stats)
+    }
+}
+
+/// check both trees, repair them unless dry run.
+/// run it with the server stopped.
+pub(crate) fn fsck(dry_run: bool) -> anyhow::Result<Report> {
+    let content = DB.open_tree(CONTENT_TREE)?;
+    let root_list = DB.open_tree(ROOT_LIST)?;
+    let mut report = Report::default();
+    // parents sort before their replies, so one pass is enough.
+    let mut alive = HashSet::new();
+    let mut threads: BTreeMap<IVec, NodeBody> = BTreeMap::new();
+    for item in content.iter() {
+        let (id, body) = item?;
+        let is_node = !id.is_empty() && id.len().is_multiple_of(ID_LEN);
+        if !is_node || (id.len() > ID_LEN && !alive.contains(&id[..id.len() - ID_LEN])) {
+            report.orphans.push(id);
+            continue;
+        }
+        // alive even if unreadable, its replies are not orphans
+        alive.insert(id.clone());
+        let body = match NodeBody::decode(&body) {
+            Ok(body) => body,
+            Err(e) => {
+                log::warn!("[fsck] node {:?} unreadable, skipped: {}.", id, e);
+                continue;
+            }
+        };
+        // last reply is not checked, bump limits leave it behind on purpose
+        if id.len() == ID_LEN {
+            threads.insert(id.clone(), body);
+        }
+    }
+    let mut root_batch = Batch::default();
+    let mut content_batch = Batch::default();
+    for item in root_list.iter() {
+        let (id, _body) = item?;
+        if !alive.contains(&id) {
+            root_batch.remove(id.clone());
+            report.root_without_content.push(id);
+        } else if archive::contains(&id)? {
+            root_batch.remove(id.clone());
+            report.archived_in_root.push(id);
+        }
+    }
+    let thread_ids: Vec<IVec> = threads.keys().cloned().collect();
+    for (id, top) in threads {
+        if archive::contains(&id)? {
+            continue;
+        }
+        let bin = IVec::from(top.encode());
+        match root_list.get(&id)? {
+            None => report.content_without_root.push(id.clone()),
+            Some(root) if root != bin => report.stale.push(id.clone()),
+            Some(_) => continue,
+        }
+        root_batch.insert(id, bin);
+    }
+    for id in report.orphans.iter() {
+        content_batch.remove(id.clone());
+    }
+    if !dry_run && !report.is_clean() {
+        content.apply_batch(content_batch)?;
+        root_list.apply_batch(root_batch)?;
+    }
+    // ordered after root list is repaired
+    report.stale_order = order::check(dry_run)?;
+    // counted after orphans are gone
+    report.stale_stats = aggregate::check(&thread_ids, dry_run)?;
+    if !dry_run && !report.is_clean() {
+        DB.flush()?;
+    }
+    Ok(report)
+}
@@ -0,0 +1,73 @@
+//! what nodes said before they were edited.
+
+use super::{next_id, NodeBody, CONTENT_TREE, DB};
+use chrono::Utc;
+use sled::{
+    transaction::{ConflictableTransactionResult, TransactionalTree},
+    IVec, Tree,
+};
+use terminus_types::{action::Response, encode_id, Error, NodeId, Revision, ID_LEN};
+
+/// node id and when it was replaced, to the revision.
+const HISTORY: &str = "history";
+
+/// revisions of id alone, not of replies under it.
+fn revisions_of(id: &[u8]) -> anyhow::Result<Vec<(IVec, IVec)>> {
+    let mut revisions = Vec::new();
+    for item in DB.open_tree(HISTORY)?.scan_prefix(id) {
+        let (key, revision) = item?;
+        if key.len() == id.len() + ID_LEN {
+            revisions.push((key, revision));
+        }
+    }
+    Ok(revisions)
+}
+
+pub(super) fn tree() -> sled::Result<Tree> {
+    DB.open_tree(HISTORY)
+}
+
+/// where the next revision of id goes, taken before the transaction that keeps it.
+pub(super) fn key(id: &[u8]) -> Vec<u8> {
+    let mut key = id.to_owned();
+    key.extend_from_slice(&encode_id(next_id()));
+    key
+}
+
+/// keep what node said, in the transaction that edits it.
+pub(super) fn keep(
+    history: &TransactionalTree,
+    key: &[u8],
+    title: String,
+    content: String,
+) -> ConflictableTransactionResult<(), Error> {
+    let revision = Revision {
+        title,
+        content,
+        replaced: Utc::now(),
+    };
+    let revision = bincode::serialize(&revision).expect("should always serialize success");
+    history.insert(key, revision)?;
+    Ok(())
+}
+
+/// revisions to drop with a node forgotten after delete, taken before the transaction that drops them.
+pub(super) fn keys_of(id: &[u8]) -> anyhow::Result<Vec<IVec>> {
+    Ok(revisions_of(id)?.into_iter().map(|(key, _)| key).collect())
+}
+
+/// deleted nodes tell nothing.
+pub(crate) fn history(id: NodeId) -> anyhow::Result<Response> {
+    if id.is_empty() || !id.len().is_multiple_of(ID_LEN) {
+        return Ok(Response::Err(Error::IdInvalid));
+    }
+    match DB.open_tree(CONTENT_TREE)?.get(&id)? {
+        Some(body) if !NodeBody::decode(&body)?.deleted => {}
+        _ => return Ok(Response::Err(Error::NodeNotExist)),
+    }
+    let mut revisions = Vec::new();
+    for (_, revision) in revisions_of(&id)? {
+        revisions.push(bincode::deserialize(&revision)?);
+    }
+    Ok(Response::History { id, revisions })
+}
@@ -0,0 +1,638 @@
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sled::{
+    transaction::{
+        ConflictableTransactionError, ConflictableTransactionResult, TransactionError, TransactionResult,
+        Transactional, TransactionalTree,
+    },
+    Db, IVec,
+};
+use std::{
+    net::IpAddr,
+    ops::Bound,
+    sync::Mutex,
+    time::{Duration as StdDuration, SystemTime, UNIX_EPOCH},
+};
+use terminus_types::{
+    action::{Cursor, Envelope, ListTarget, Response},
+    decode_id, Author, Draft, Error, Node, NodeId, Patch, ID_LEN,
+};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::{
+    config,
+    filter::{self, Verdict},
+};
+
+mod admin;
+mod aggregate;
+mod archive;
+mod fsck;
+mod history;
+mod moderate;
+mod order;
+mod schema;
+mod tombstone;
+
+pub(crate) use admin::{compact, export, import, stats};
+pub(crate) use aggregate::stats_of;
+pub(crate) use archive::list_archive;
+pub(crate) use fsck::fsck;
+pub(crate) use history::history;
+pub(crate) use moderate::{held, held_id, key_of, mod_log, moderate, moderate_offline};
+pub(crate) use schema::{check_schema, migrate};
+
+#[derive(Deserialize, Serialize, Clone)]
+struct NodeBody {
+    pub title: String,
+    pub author: Author,
+    pub content: String,
+    pub publish_time: DateTime<Utc>,
+    pub last_reply: DateTime<Utc>,
+    pub edited: bool,
+    pub sticky: bool,
+    pub deleted: bool,
+}
+
+impl NodeBody {
+    pub(crate) fn match_pass(&self, other: &Author) -> bool {
+        self.author.match_pass(&other.name, other.pass.get_pass())
+    }
+}
+
+impl From<NodeBody> for IVec {
+    fn from(val: NodeBody) -> Self {
+        val.encode().into()
+    }
+}
+
+fn disperse_node(node: Node) -> anyhow::Result<(NodeId, NodeBody)> {
+    let Node {
+        id,
+        title,
+        author,
+        content,
+        publish_time,
+        last_reply,
+        edited,
+        sticky,
+        deleted,
+    } = node;
+    let body = NodeBody {
+        title,
+        author,
+        content,
+        publish_time,
+        last_reply,
+        edited,
+        sticky,
+        deleted,
+    };
+    Ok((id, body))
+}
+
+fn rebuild_node(id: &[u8], body: NodeBody) -> Node {
+    let NodeBody {
+        title,
+        author,
+        content,
+        publish_time,
+        last_reply,
+        edited,
+        sticky,
+        deleted,
+    } = body;
+    Node {
+        id: id.to_owned(),
+        title,
+        author,
+        content,
+        publish_time,
+        last_reply,
+        edited,
+        sticky,
+        deleted,
+    }
+}
+
+fn assemble_node(id: &[u8], body: &[u8]) -> anyhow::Result<Node> {
+    Ok(rebuild_node(id, NodeBody::decode(body)?))
+}
+
+const CONTENT_TREE: &str = "content";
+const ROOT_LIST: &str = "root_list";
+static DB: Lazy<Db> = Lazy::new(|| sled::open(&config::current().database).unwrap());
+
+/// last id given, ids never go back even if clock does.
+static LAST_ID: Lazy<Mutex<u128>> = Lazy::new(|| Mutex::new(0));
+
+/// ready the store before it changes, with the server or from the command line.
+/// drift found is only told, fsck repairs it.
+pub(crate) fn prepare() -> anyhow::Result<()> {
+    seed_id()?;
+    let uncounted = aggregate::uncounted()?;
+    if uncounted > 0 {
+        log::warn!("[store] {} threads have no stats, run fsck.", uncounted);
+    }
+    let unordered = order::check(true)?.len();
+    if unordered > 0 {
+        log::warn!("[store] list order of {} threads out of step, run fsck.", unordered);
+    }
+    Ok(())
+}
+
+/// start ids after the newest node posted or held, before any is given,
+/// so a clock set back across a restart never gives an id twice.
+fn seed_id() -> anyhow::Result<()> {
+    let mut newest = 0;
+    for tree in [CONTENT_TREE, moderate::HELD] {
+        for key in DB.open_tree(tree)?.iter().keys() {
+            let key = key?;
+            if let Some(start) = key.len().checked_sub(ID_LEN) {
+                newest = newest.max(decode_id(&key[start..])?);
+            }
+        }
+    }
+    let mut last = LAST_ID.lock().unwrap();
+    *last = newest.max(*last);
+    Ok(())
+}
+
+/// seconds in high 64 bits, nanoseconds in the next 32, low 32 bits break ties.
+fn next_id() -> u128 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Hello John titor!");
+    let id = ((now.as_secs() as u128) << 64) | ((now.subsec_nanos() as u128) << 32);
+    let mut last = LAST_ID.lock().unwrap();
+    *last = id.max(*last + 1);
+    *last
+}
+
+/// user errors abort a transaction, and become the response.
+fn abort<T>(e: Error) -> ConflictableTransactionResult<T, Error> {
+    Err(ConflictableTransactionError::Abort(e))
+}
+
+fn read_body(body: &[u8]) -> ConflictableTransactionResult<NodeBody, Error> {
+    NodeBody::decode(body).or_else(|e| {
+        log::error!("node body broken: {}.", e);
+        abort(Error::Internal)
+    })
+}
+
+/// keep the root entry of a live thread and its order in step, archived ones have none.
+fn relist(
+    root_list: &TransactionalTree,
+    root_order: &TransactionalTree,
+    id: &[u8],
+    body: NodeBody,
+) -> ConflictableTransactionResult<(), Error> {
+    if id.len() != ID_LEN {
+        return Ok(());
+    }
+    if let Some(old) = root_list.get(id)? {
+        root_order.remove(order::root_key(id, &read_body(&old)?))?;
+        root_order.insert(order::root_key(id, &body), IVec::default())?;
+        root_list.insert(id, body)?;
+    }
+    Ok(())
+}
+
+fn commit(res: TransactionResult<Response, Error>) -> anyhow::Result<Response> {
+    match res {
+        Ok(resp) => Ok(resp),
+        Err(TransactionError::Abort(e)) => Ok(Response::Err(e)),
+        Err(TransactionError::Storage(e)) => Err(e.into()),
+    }
+}
+
+/// address is kept for moderators to ban.
+pub(crate) fn post(mut draft: Draft, sage: bool, address: IpAddr) -> anyhow::Result<Response> {
+    draft.author.mask();
+    if !draft.parent.len().is_multiple_of(ID_LEN) {
+        return Ok(Response::Err(Error::IdInvalid));
+    }
+    if let Some(e) = moderate::refused(draft.author.pass.get_pass(), &draft.parent, &address)? {
+        log::warn!("[post] refused from {}: {}.", address, e);
+        return Ok(Response::Err(e));
+    }
+    let mut node = draft.into_node(next_id());
+    let outcome = filter::apply(&mut [&mut node.title, &mut node.content]);
+    moderate::count_hits(&outcome.hits)?;
+    if let Verdict::Reject(reason) = outcome.verdict {
+        log::warn!("[post] rejected from {}: {}.", address, reason);
+        return Ok(Response::Err(Error::Rejected { reason }));
+    }
+    if let Err(e) = node.validate(&config::current().limits) {
+        return Ok(Response::Err(e));
+    }
+    if let Verdict::Hold(reason) = outcome.verdict {
+        log::info!("[post] held {}: {}.", node.last_id()?, reason);
+        moderate::hold_post(node, sage, reason, &address)?;
+        return Ok(Response::Err(Error::Held));
+    }
+    log::info!("[post] new post: {}.", node.last_id()?);
+    let res = publish(node, sage)?;
+    if let Response::Post(ref node) = res {
+        moderate::record_source(&node.id, &address)?;
+    }
+    Ok(res)
+}
+
+/// put a node on board, replies need their parent alive and thread not archived nor locked.
+/// threads over max live go to archive, and over reply cap get locked.
+/// sage replies leave last reply of the thread as it is.
+fn publish(node: Node, sage: bool) -> anyhow::Result<Response> {
+    // should have one
+    let top_id_bin = node.top_id_bin()?;
+    let is_top_level = node.is_top_level();
+    let parent = node.parent_id().to_owned();
+    let (id, body) = disperse_node(node.clone())?;
+    let threads = config::current().threads.clone();
+    let content = DB.open_tree(CONTENT_TREE)?;
+    let root_list = DB.open_tree(ROOT_LIST)?;
+    let root_order = order::root_tree()?;
+    let aggregates = aggregate::tree()?;
+    let archive = archive::tree()?;
+    let locks = moderate::locks()?;
+    let trees = (&content, &root_list, &root_order, &aggregates, &archive, &locks);
+    let res = trees.transaction(|(content, root_list, root_order, aggregates, archive, locks)| {
+        if content.get(&id)?.is_some() {
+            return abort(Error::NodeExist);
+        }
+        // replies to the thread before this one, if it locks at this one
+        let mut locked = None;
+        if is_top_level {
+            root_list.insert(id.as_slice(), body.clone())?;
+            root_order.insert(order::root_key(&id, &body), IVec::default())?;
+        } else {
+            if archive.get(&top_id_bin)?.is_some() {
+                return abort(Error::Archived);
+            }
+            if locks.get(&top_id_bin)?.is_some() {
+                return abort(Error::Locked);
+            }
+            // replies need their parent alive
+            match content.get(&parent)? {
+                Some(parent) if !read_body(&parent)?.deleted => {}
+                _ => return abort(Error::NodeNotExist),
+            }
+            let mut top = match content.get(&top_id_bin)? {
+                Some(top) => read_body(&top)?,
+                None => return abort(Error::IdInvalid),
+            };
+            // tombstones are not counted
+            let replies = aggregate::read(aggregates, &top_id_bin)?.replies as usize;
+            if !sage && threads.bump_limit.is_none_or(|limit| replies < limit) {
+                top.last_reply = body.publish_time;
+                relist(root_list, root_order, &top_id_bin, top.clone())?;
+                content.insert(top_id_bin.as_slice(), top)?;
+            }
+            if threads.reply_cap.is_some_and(|cap| replies + 1 >= cap) {
+                locks.insert(top_id_bin.as_slice(), IVec::default())?;
+                locked = Some(replies + 1);
+            }
+        }
+        content.insert(id.as_slice(), body.clone())?;
+        aggregate::add(aggregates, &top_id_bin, &[(&id, &body.author)])?;
+        Ok((Response::Post(node.clone()), locked))
+    });
+    if let Ok((_, Some(replies))) = res {
+        let thread = decode_id(&top_id_bin)?;
+        log::info!("[post] thread {} locked at {} replies.", thread, replies);
+    }
+    let res = commit(res.map(|(res, _)| res))?;
+    if is_top_level && matches!(res, Response::Post(_)) {
+        archive::overflow()?;
+    }
+    Ok(res)
+}
+
+/// cut list to limit, and give the cursor of last one if more left.
+fn paginate<F>(list: &mut Vec<Node>, limit: usize, cursor_of: F) -> anyhow::Result<Option<Cursor>>
+where
+    F: Fn(&Node) -> anyhow::Result<Vec<u8>>,
+{
+    if list.len() <= limit {
+        return Ok(None);
+    }
+    list.truncate(limit);
+    match list.last() {
+        Some(last) => Ok(Some(Cursor::new(cursor_of(last)?))),
+        None => Ok(None),
+    }
+}
+
+/// root is listed sticky first, then by last reply, newest first.
+/// cursor is the order key of the last one.
+pub(crate) fn list_root(limit: usize, cursor: Option<Cursor>) -> anyhow::Result<Response> {
+    let (keys, next) = match order::page(&order::root_tree()?, order::ROOT_KEY_LEN, limit, cursor)? {
+        Some(page) => page,
+        None => return Ok(Response::Err(Error::CursorInvalid)),
+    };
+    let tree = DB.open_tree(ROOT_LIST)?;
+    let mut res = Vec::new();
+    for key in keys {
+        let id = order::id_of(&key);
+        // gone since the page was read
+        if let Some(body) = tree.get(id)? {
+            res.push(assemble_node(id, &body)?);
+        }
+    }
+    Ok(Response::List {
+        target: ListTarget::Root,
+        nodes: res,
+        next,
+    })
+}
+
+/// node is listed in key order, cursor is the last key.
+pub(crate) fn list(root: NodeId, limit: usize, cursor: Option<Cursor>) -> anyhow::Result<Response> {
+    let tree = DB.open_tree(CONTENT_TREE)?;
+    let list = match cursor {
+        Some(ref cursor) if !cursor.as_bytes().starts_with(&root) => {
+            return Ok(Response::Err(Error::CursorInvalid));
+        }
+        Some(cursor) => tree.range((Bound::Excluded(cursor.as_bytes().to_vec()), Bound::Unbounded)),
+        None => tree.range(root.clone()..),
+    };
+    let mut res = Vec::new();
+    // one more to know if there is a next page
+    for item in list.take(limit.saturating_add(1)) {
+        let (id, body) = item?;
+        if !id.starts_with(&root) {
+            break;
+        }
+        res.push(assemble_node(&id, &body)?);
+    }
+    let next = paginate(&mut res, limit, |node| Ok(node.id.clone()))?;
+    Ok(Response::List {
+        target: ListTarget::Node(root),
+        nodes: res,
+        next,
+    })
+}
+
+/// content, root list, root order, thread stats and where the action keeps what was there,
+/// in one transaction.
+type Trees<'a> = (
+    &'a TransactionalTree,
+    &'a TransactionalTree,
+    &'a TransactionalTree,
+    &'a TransactionalTree,
+    &'a TransactionalTree,
+);
+
+fn delete_or_update<F>(
+    id: NodeId,
+    author: Author,
+    action: &str,
+    kept: &sled::Tree,
+    action_fun: F,
+) -> anyhow::Result<Response>
+where
+    F: Fn(Trees, &[u8], NodeBody) -> ConflictableTransactionResult<Response, Error>,
+{
+    if author.is_masked() {
+        return Ok(Response::Err(Error::NeedUnMaskPass));
+    }
+    if id.is_empty() || !id.len().is_multiple_of(ID_LEN) {
+        return Ok(Response::Err(Error::IdInvalid));
+    }
+    if archive::contains(&id)? {
+        return Ok(Response::Err(Error::Archived));
+    }
+    let target_id = decode_id(&id[id.len() - ID_LEN..])?;
+    log::info!("[{}] node {}.", action, target_id);
+    // really do
+    let content = DB.open_tree(CONTENT_TREE)?;
+    let root_list = DB.open_tree(ROOT_LIST)?;
+    let root_order = order::root_tree()?;
+    let aggregates = aggregate::tree()?;
+    let trees = (&content, &root_list, &root_order, &aggregates, kept);
+    let res = trees.transaction(|(content, root_list, root_order, aggregates, kept)| {
+        let body = match content.get(&id)? {
+            Some(body) => read_body(&body)?,
+            None => {
+                log::warn!("[{}] node {} not found.", action, target_id);
+                return abort(Error::NodeNotExist);
+            }
+        };
+        if body.deleted {
+            return abort(Error::NodeNotExist);
+        }
+        if !body.match_pass(&author) {
+            log::warn!("[{}] node {} pass not match.", action, target_id);
+            return abort(Error::PassNotMatch);
+        }
+        action_fun((content, root_list, root_order, aggregates, kept), &id, body)
+    });
+    commit(res)
+}
+
+/// leave a tombstone, replies stay under it.
+pub(crate) fn delete(node: Node) -> anyhow::Result<Response> {
+    let window = config::current().delete_window();
+    let now = Utc::now();
+    let replies = match node.id.get(..ID_LEN) {
+        Some(top) => aggregate::replies_of(&DB.open_tree(CONTENT_TREE)?, top)?,
+        None => Vec::new(),
+    };
+    delete_or_update(
+        node.id,
+        node.author,
+        "delete",
+        &tombstone::tree()?,
+        |(content, root_list, root_order, aggregates, deleted), id, mut body| {
+            if now - body.publish_time > window {
+                return abort(Error::DeleteLimitOverdue);
+            }
+            let buried = tombstone::bury(&mut body, now);
+            content.insert(id, body.clone())?;
+            relist(root_list, root_order, id, body.clone())?;
+            aggregate::remove(aggregates, content, &id[..ID_LEN], &[(id, buried.author())], &replies)?;
+            tombstone::keep(deleted, id, &buried)?;
+            Ok(Response::Delete(rebuild_node(id, body)))
+        },
+    )
+}
+
+/// only title and content can change, times and author stay.
+/// what was there before is kept in history.
+pub(crate) fn update(patch: Patch) -> anyhow::Result<Response> {
+    let Patch {
+        id,
+        author,
+        mut title,
+        mut content,
+    } = patch;
+    let outcome = {
+        let mut fields: Vec<&mut String> = title.iter_mut().chain(content.iter_mut()).collect();
+        filter::apply(&mut fields)
+    };
+    moderate::count_hits(&outcome.hits)?;
+    let hold = match outcome.verdict {
+        Verdict::Pass => None,
+        Verdict::Reject(reason) => {
+            log::warn!("[update] rejected: {}.", reason);
+            return Ok(Response::Err(Error::Rejected { reason }));
+        }
+        Verdict::Hold(reason) => Some(reason),
+    };
+    let config = config::current();
+    let held_id = id.clone();
+    let revision_key = history::key(&id);
+    let res = delete_or_update(
+        id,
+        author,
+        "update",
+        &history::tree()?,
+        |(content_tree, root_list, root_order, _, history), id, mut body| {
+            if let Some(window) = config.edit_window() {
+                if Utc::now() - body.publish_time > window {
+                    return abort(Error::EditLimitOverdue);
+                }
+            }
+            let (old_title, old_content) = (body.title.clone(), body.content.clone());
+            let mut changed = false;
+            if let Some(title) = title.as_ref().filter(|title| *title != &body.title) {
+                body.title = title.clone();
+                changed = true;
+            }
+            if let Some(content) = content.as_ref().filter(|content| *content != &body.content) {
+                body.content = content.clone();
+                changed = true;
+            }
+            if !changed {
+                return abort(Error::NoChange);
+            }
+            body.edited = true;
+            let node = rebuild_node(id, body.clone());
+            if let Err(e) = node.validate(&config.limits) {
+                return abort(e);
+            }
+            // all checked, kept aside after
+            if hold.is_some() {
+                return abort(Error::Held);
+            }
+            content_tree.insert(id, body.clone())?;
+            relist(root_list, root_order, id, body)?;
+            history::keep(history, &revision_key, old_title, old_content)?;
+            Ok(Response::Update(node))
+        },
+    )?;
+    if let (Response::Err(Error::Held), Some(reason)) = (&res, hold) {
+        log::info!("[update] held: {}.", reason);
+        moderate::hold_edit(&held_id, title, content, reason)?;
+    }
+    Ok(res)
+}
+
+async fn forward(mut inbox: sled::Subscriber, s: UnboundedSender<Envelope>) -> anyhow::Result<()> {
+    while let Some(event) = (&mut inbox).await {
+        match event {
+            sled::Event::Insert { key, value } => {
+                let node = assemble_node(&key, &value)?;
+                let resp = Response::Update(node);
+                s.send(Envelope::Event(resp))?;
+            }
+            sled::Event::Remove { key } => {
+                let mut node = Node::empty_node();
+                node.id = key.to_vec();
+                let resp = Response::Delete(node);
+                s.send(Envelope::Event(resp))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// push every change on board, for clients can not subscribe.
+pub(crate) async fn notify_channel(s: UnboundedSender<Envelope>) -> anyhow::Result<()> {
+    let inbox = DB.open_tree(CONTENT_TREE)?.watch_prefix([]);
+    forward(inbox, s).await
+}
+
+/// push changes of target only.
+/// root gets new, bumped and deleted threads and their stats, node gets its sub tree.
+pub(crate) async fn watch(target: ListTarget, s: UnboundedSender<Envelope>) -> anyhow::Result<()> {
+    let inbox = match target {
+        ListTarget::Root => {
+            let threads = forward(DB.open_tree(ROOT_LIST)?.watch_prefix([]), s.clone());
+            tokio::try_join!(threads, aggregate::forward(s))?;
+            return Ok(());
+        }
+        ListTarget::Node(id) => DB.open_tree(CONTENT_TREE)?.watch_prefix(id),
+        // archive only changes when threads go, nothing to push
+        ListTarget::Archive => return Ok(()),
+    };
+    forward(inbox, s).await
+}
+
+/// drop tombstones, archive threads over max live and delete those archived too long.
+fn upkeep() -> anyhow::Result<()> {
+    let tombstones = tombstone::purge_once()?;
+    let archived = archive::overflow()?;
+    let expired = archive::expire()?;
+    if tombstones + archived + expired > 0 {
+        log::info!(
+            "[purge] {} tombstones removed, {} threads archived, {} archived nodes deleted.",
+            tombstones,
+            archived,
+            expired
+        );
+    }
+    Ok(())
+}
+
+/// upkeep every hour.
+pub(crate) async fn purge() {
+    let mut interval = tokio::time::interval(StdDuration::from_secs(60 * 60));
+    loop {
+        interval.tick().await;
+        match tokio::task::spawn_blocking(upkeep).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => log::error!("[purge] failed: {}.", e),
+            Err(e) => log::error!("[purge] panicked: {}.", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::{assemble_node, disperse_node, next_id, paginate};
+
+    use super::{Author, Draft, Node};
+
+    #[test]
+    fn disperse_and_assemble() {
+        let me = Author::new("Me!".to_string(), "ME!ME!ME!".to_string());
+        let node = Draft::new(&[], "Hi".to_string(), me, "nothing".to_string()).into_node(90);
+        let origin = format!("{:?}", &node);
+        let (id, body) = disperse_node(node).unwrap();
+        let node = assemble_node(&id, &body.encode()).unwrap();
+        let back = format!("{:?}", &node);
+        assert_eq!(origin, back);
+    }
+
+    #[test]
+    fn paginate_cut() {
+        let me = Author::new("Me!".to_string(), "ME!ME!ME!".to_string());
+        let mut list: Vec<Node> = (0..3)
+            .map(|id| Draft::new(&[], "Hi".to_string(), me.clone(), "nothing".to_string()).into_node(id))
+            .collect();
+        let last = list[1].id.clone();
+        let next = paginate(&mut list, 2, |node| Ok(node.id.clone())).unwrap();
+        assert_eq!(2, list.len());
+        assert_eq!(Some(last.as_slice()), next.as_ref().map(|cursor| cursor.as_bytes()));
+        assert!(paginate(&mut list, 2, |node| Ok(node.id.clone())).unwrap().is_none());
+    }
+
+    #[test]
+    fn id_monotonic() {
+        let ids: Vec<u128> = (0..1000).map(|_| next_id()).collect();
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
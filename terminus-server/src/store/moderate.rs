@@ -0,0 +1,528 @@
+//! moderator actions, and the locks and bans they leave.
+
+use super::{
+    abort, aggregate, archive, assemble_node, disperse_node, history, next_id, order, publish, read_body, rebuild_node,
+    relist, schema::Rewrite, tombstone, NodeBody, CONTENT_TREE, DB, ROOT_LIST,
+};
+use crate::config;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sled::transaction::{TransactionError, Transactional};
+use std::{convert::TryInto, fmt, net::IpAddr};
+use terminus_types::{
+    action::{Moderation, Response},
+    decode_id, encode_id, Author, Error, Node, ID_LEN,
+};
+
+/// thread id to log id of its lock.
+const LOCKS: &str = "locks";
+/// banned tripcode or address to log id of the ban.
+const BANS: &str = "bans";
+/// log id to record, in time order.
+const MOD_LOG: &str = "mod_log";
+/// node id to the address it was posted from, never sent to clients.
+const SOURCES: &str = "sources";
+/// node id to a post or edit held by filter.
+pub(super) const HELD: &str = "held";
+/// filter rule name to times it matched, big endian.
+const FILTER_HITS: &str = "filter_hits";
+
+/// waiting for a moderator.
+#[derive(Serialize, Deserialize)]
+pub(super) enum Held {
+    /// a new node, body encoded as on board.
+    Post { body: Vec<u8>, reason: String },
+    /// changes to a node on board.
+    Edit {
+        title: Option<String>,
+        content: Option<String>,
+        reason: String,
+    },
+    /// a new reply that should not bump its thread, as post.
+    Sage { body: Vec<u8>, reason: String },
+}
+
+/// one moderation done.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Record {
+    pub time: DateTime<Utc>,
+    pub name: String,
+    pub key: String,
+    pub action: Moderation,
+    pub reason: String,
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (what, id) = match &self.action {
+            Moderation::Check | Moderation::Held => return write!(f, "{} check", self.time),
+            Moderation::Delete(id) => ("delete", id),
+            Moderation::Lock(id) => ("lock", id),
+            Moderation::Unlock(id) => ("unlock", id),
+            Moderation::BanTripcode(id) => ("ban tripcode of", id),
+            Moderation::BanAddress(id) => ("ban address of", id),
+            Moderation::Stick(id) => ("stick", id),
+            Moderation::Unstick(id) => ("unstick", id),
+            Moderation::Approve(id) => ("approve", id),
+            Moderation::Discard(id) => ("discard", id),
+            Moderation::Restore(id) => ("restore", id),
+        };
+        let last = id
+            .len()
+            .checked_sub(ID_LEN)
+            .and_then(|start| decode_id(&id[start..]).ok());
+        write!(
+            f,
+            "{} {}#{} {} {}: {}",
+            self.time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            self.name,
+            &self.key[..self.key.len().min(8)],
+            what,
+            last.unwrap_or_default(),
+            self.reason
+        )
+    }
+}
+
+/// masked pass of author, what moderators are known by.
+pub(crate) fn key_of(mut author: Author) -> String {
+    author.mask();
+    author.pass.get_pass().to_owned()
+}
+
+fn tripcode_ban(key: &str) -> Vec<u8> {
+    [b"tripcode:", key.as_bytes()].concat()
+}
+
+fn address_ban(address: &IpAddr) -> Vec<u8> {
+    format!("address:{}", address).into_bytes()
+}
+
+/// why a post can not be taken, if any.
+pub(super) fn refused(key: &str, parent: &[u8], address: &IpAddr) -> anyhow::Result<Option<Error>> {
+    let bans = DB.open_tree(BANS)?;
+    if bans.contains_key(tripcode_ban(key))? || bans.contains_key(address_ban(address))? {
+        return Ok(Some(Error::Banned));
+    }
+    if let Some(top) = parent.get(..ID_LEN) {
+        if archive::contains(top)? {
+            return Ok(Some(Error::Archived));
+        }
+        if DB.open_tree(LOCKS)?.contains_key(top)? {
+            return Ok(Some(Error::Locked));
+        }
+    }
+    Ok(None)
+}
+
+/// locked threads, posts lock them at reply cap in the same transaction.
+pub(super) fn locks() -> sled::Result<sled::Tree> {
+    DB.open_tree(LOCKS)
+}
+
+/// banned tripcodes and addresses, to the log id of each ban.
+pub(super) fn bans() -> sled::Result<sled::Tree> {
+    DB.open_tree(BANS)
+}
+
+/// node id to the address it was posted from.
+pub(super) fn sources() -> sled::Result<sled::Tree> {
+    DB.open_tree(SOURCES)
+}
+
+pub(super) fn unlock(id: &[u8]) -> anyhow::Result<()> {
+    DB.open_tree(LOCKS)?.remove(&id[..ID_LEN])?;
+    Ok(())
+}
+
+pub(super) fn record_source(id: &[u8], address: &IpAddr) -> anyhow::Result<()> {
+    DB.open_tree(SOURCES)?.insert(id, address.to_string().as_bytes())?;
+    Ok(())
+}
+
+pub(super) fn forget_source(id: &[u8]) -> anyhow::Result<()> {
+    DB.open_tree(SOURCES)?.remove(id)?;
+    Ok(())
+}
+
+/// leave tombstones of node and all replies under it, they are restored together.
+fn delete_tree(id: &[u8]) -> anyhow::Result<Option<Error>> {
+    let content_tree = DB.open_tree(CONTENT_TREE)?;
+    let root_list = DB.open_tree(ROOT_LIST)?;
+    let root_order = order::root_tree()?;
+    let aggregates = aggregate::tree()?;
+    let deleted = tombstone::tree()?;
+    // replies posted meanwhile are left alive
+    let ids = content_tree.scan_prefix(id).keys().collect::<sled::Result<Vec<_>>>()?;
+    let replies = aggregate::replies_of(&content_tree, id)?;
+    let now = Utc::now();
+    let trees = (&content_tree, &root_list, &root_order, &aggregates, &deleted);
+    let res = trees.transaction(|(content, root_list, root_order, aggregates, deleted)| {
+        if content.get(id)?.is_none() {
+            return abort(Error::NodeNotExist);
+        }
+        let mut buried = Vec::new();
+        for key in ids.iter() {
+            let mut body = match content.get(key)? {
+                Some(body) => read_body(&body)?,
+                None => continue,
+            };
+            // deleted before, restored on its own
+            if body.deleted {
+                continue;
+            }
+            let gone = tombstone::bury(&mut body, now);
+            tombstone::keep(deleted, key, &gone)?;
+            buried.push((key.clone(), gone));
+            content.insert(key, body.clone())?;
+            relist(root_list, root_order, key, body)?;
+        }
+        let gone: Vec<_> = buried
+            .iter()
+            .map(|(key, buried)| (key.as_ref(), buried.author()))
+            .collect();
+        aggregate::remove(aggregates, content, &id[..ID_LEN], &gone, &replies)?;
+        Ok(())
+    });
+    match res {
+        Ok(()) => Ok(None),
+        Err(TransactionError::Abort(e)) => Ok(Some(e)),
+        Err(TransactionError::Storage(e)) => Err(e.into()),
+    }
+}
+
+/// threads are updated in both trees, so root watchers see it.
+/// edits give the key of a revision, to keep what node said before them.
+fn change_body<F>(id: &[u8], revision: Option<&[u8]>, change: F) -> anyhow::Result<Option<Error>>
+where
+    F: Fn(&mut NodeBody),
+{
+    let content = DB.open_tree(CONTENT_TREE)?;
+    let root_list = DB.open_tree(ROOT_LIST)?;
+    let root_order = order::root_tree()?;
+    let history = history::tree()?;
+    let trees = (&content, &root_list, &root_order, &history);
+    let res = trees.transaction(|(content, root_list, root_order, history)| {
+        let mut body = match content.get(id)? {
+            Some(body) => read_body(&body)?,
+            None => return abort(Error::NodeNotExist),
+        };
+        if body.deleted {
+            return abort(Error::NodeNotExist);
+        }
+        if let Some(key) = revision {
+            history::keep(history, key, body.title.clone(), body.content.clone())?;
+        }
+        change(&mut body);
+        content.insert(id, body.clone())?;
+        relist(root_list, root_order, id, body)?;
+        Ok(())
+    });
+    match res {
+        Ok(()) => Ok(None),
+        Err(TransactionError::Abort(e)) => Ok(Some(e)),
+        Err(TransactionError::Storage(e)) => Err(e.into()),
+    }
+}
+
+fn apply_edit(body: &mut NodeBody, title: &Option<String>, content: &Option<String>) {
+    if let Some(title) = title {
+        body.title = title.clone();
+    }
+    if let Some(content) = content {
+        body.content = content.clone();
+    }
+    body.edited = true;
+}
+
+pub(super) fn hold_post(node: Node, sage: bool, reason: String, address: &IpAddr) -> anyhow::Result<()> {
+    let (id, body) = disperse_node(node)?;
+    record_source(&id, address)?;
+    let body = body.encode();
+    let held = if sage {
+        Held::Sage { body, reason }
+    } else {
+        Held::Post { body, reason }
+    };
+    DB.open_tree(HELD)?.insert(id, bincode::serialize(&held)?)?;
+    Ok(())
+}
+
+/// a later edit of the same node takes the place.
+pub(super) fn hold_edit(id: &[u8], title: Option<String>, content: Option<String>, reason: String) -> anyhow::Result<()> {
+    let held = Held::Edit { title, content, reason };
+    DB.open_tree(HELD)?.insert(id, bincode::serialize(&held)?)?;
+    Ok(())
+}
+
+/// held nodes as they would be on board, edits of nodes gone are left out.
+pub(crate) fn held() -> anyhow::Result<Vec<(Node, String)>> {
+    let content_tree = DB.open_tree(CONTENT_TREE)?;
+    let mut nodes = Vec::new();
+    for item in DB.open_tree(HELD)?.iter() {
+        let (id, held) = item?;
+        match bincode::deserialize(&held)? {
+            Held::Post { body, reason } | Held::Sage { body, reason } => {
+                nodes.push((assemble_node(&id, &body)?, reason))
+            }
+            Held::Edit { title, content, reason } => {
+                if let Some(body) = content_tree.get(&id)? {
+                    let mut body = NodeBody::decode(&body)?;
+                    apply_edit(&mut body, &title, &content);
+                    nodes.push((rebuild_node(&id, body), reason));
+                }
+            }
+        }
+    }
+    Ok(nodes)
+}
+
+/// put on board what is held, it stays held if that fails.
+fn approve(id: &[u8]) -> anyhow::Result<Option<Error>> {
+    let held_tree = DB.open_tree(HELD)?;
+    let held = match held_tree.get(id)? {
+        Some(held) => bincode::deserialize(&held)?,
+        None => return Ok(Some(Error::NodeNotExist)),
+    };
+    let sage = matches!(held, Held::Sage { .. });
+    let failed = match held {
+        Held::Post { body, .. } | Held::Sage { body, .. } => match publish(assemble_node(id, &body)?, sage)? {
+            Response::Err(e) => Some(e),
+            _ => None,
+        },
+        Held::Edit { title, content, .. } => {
+            let key = history::key(id);
+            change_body(id, Some(&key), |body| apply_edit(body, &title, &content))?
+        }
+    };
+    if failed.is_none() {
+        held_tree.remove(id)?;
+    }
+    Ok(failed)
+}
+
+fn discard(id: &[u8]) -> anyhow::Result<Option<Error>> {
+    match DB.open_tree(HELD)?.remove(id)? {
+        Some(held) => {
+            if let Held::Post { .. } | Held::Sage { .. } = bincode::deserialize(&held)? {
+                forget_source(id)?;
+            }
+            Ok(None)
+        }
+        None => Ok(Some(Error::NodeNotExist)),
+    }
+}
+
+pub(super) fn held_tree() -> sled::Result<sled::Tree> {
+    DB.open_tree(HELD)
+}
+
+/// log id to record of moderation.
+pub(super) fn log_tree() -> sled::Result<sled::Tree> {
+    DB.open_tree(MOD_LOG)
+}
+
+/// filter rule name to times it matched.
+pub(super) fn hits_tree() -> sled::Result<sled::Tree> {
+    DB.open_tree(FILTER_HITS)
+}
+
+/// run a migration step on the body of a held post.
+pub(super) fn rewrite_held(key: &[u8], value: &[u8], rewrite: fn(&[u8], &[u8]) -> Rewrite) -> Rewrite {
+    let (reason, body, sage) = match bincode::deserialize(value)? {
+        Held::Post { body, reason } => (reason, body, false),
+        Held::Sage { body, reason } => (reason, body, true),
+        Held::Edit { .. } => return Ok(None),
+    };
+    let (key, body) = match rewrite(key, &body)? {
+        Some(rewritten) => rewritten,
+        None => return Ok(None),
+    };
+    let held = if sage {
+        Held::Sage { body, reason }
+    } else {
+        Held::Post { body, reason }
+    };
+    Ok(Some((key, bincode::serialize(&held)?)))
+}
+
+pub(super) fn count_hits(hits: &[String]) -> anyhow::Result<()> {
+    let tree = DB.open_tree(FILTER_HITS)?;
+    for name in hits.iter() {
+        tree.update_and_fetch(name.as_bytes(), |old| {
+            let old = old.and_then(|old| old.try_into().ok()).map(u64::from_be_bytes);
+            Some((old.unwrap_or(0) + 1).to_be_bytes().to_vec())
+        })?;
+    }
+    Ok(())
+}
+
+/// filter rules and times they matched, by name.
+pub(super) fn filter_hits() -> anyhow::Result<Vec<(String, u64)>> {
+    DB.open_tree(FILTER_HITS)?
+        .iter()
+        .map(|item| {
+            let (name, hits) = item?;
+            let hits = hits.as_ref().try_into().map(u64::from_be_bytes).unwrap_or(0);
+            Ok((String::from_utf8_lossy(&name).into_owned(), hits))
+        })
+        .collect()
+}
+
+fn ban(id: &[u8], by_address: bool, log_id: &[u8]) -> anyhow::Result<Option<Error>> {
+    let ban = if by_address {
+        match DB.open_tree(SOURCES)?.get(id)? {
+            Some(address) => address_ban(&String::from_utf8_lossy(&address).parse()?),
+            None => return Ok(Some(Error::SourceUnknown)),
+        }
+    } else {
+        let author = match DB.open_tree(CONTENT_TREE)?.get(id)? {
+            Some(body) => {
+                let body = NodeBody::decode(&body)?;
+                if body.deleted {
+                    tombstone::author_of(id)?.unwrap_or(body.author)
+                } else {
+                    body.author
+                }
+            }
+            None => return Ok(Some(Error::NodeNotExist)),
+        };
+        tripcode_ban(author.pass.get_pass())
+    };
+    DB.open_tree(BANS)?.insert(ban, log_id)?;
+    Ok(None)
+}
+
+pub(crate) fn moderate(author: Author, action: Moderation, reason: String) -> anyhow::Result<Response> {
+    if author.is_masked() {
+        return Ok(Response::Err(Error::NeedUnMaskPass));
+    }
+    let name = author.name.clone();
+    let key = key_of(author);
+    if !config::current().moderators.contains(&key) {
+        log::warn!("[moderate] {} is not a moderator.", name);
+        return Ok(Response::Err(Error::NotModerator));
+    }
+    act(name, key, action, reason)
+}
+
+/// name and key moderation from the command line is logged under.
+const CONSOLE: &str = "console";
+
+/// moderation from the command line, with the server stopped.
+pub(crate) fn moderate_offline(action: Moderation, reason: String) -> anyhow::Result<Response> {
+    act(CONSOLE.to_owned(), CONSOLE.to_owned(), action, reason)
+}
+
+/// full id of a held node by its last layer, as the command line shows it.
+pub(crate) fn held_id(last: u128) -> anyhow::Result<Option<Vec<u8>>> {
+    for key in DB.open_tree(HELD)?.iter().keys() {
+        let key = key?;
+        if let Some(start) = key.len().checked_sub(ID_LEN) {
+            if decode_id(&key[start..])? == last {
+                return Ok(Some(key.to_vec()));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// done by a moderator already checked, and logged.
+fn act(name: String, key: String, action: Moderation, reason: String) -> anyhow::Result<Response> {
+    let id = match &action {
+        Moderation::Check => return Ok(Response::Moderate(action)),
+        Moderation::Held => return Ok(Response::Held(held()?)),
+        Moderation::Delete(id)
+        | Moderation::Lock(id)
+        | Moderation::Unlock(id)
+        | Moderation::BanTripcode(id)
+        | Moderation::BanAddress(id)
+        | Moderation::Stick(id)
+        | Moderation::Unstick(id)
+        | Moderation::Approve(id)
+        | Moderation::Discard(id)
+        | Moderation::Restore(id) => id,
+    };
+    if id.is_empty() || !id.len().is_multiple_of(ID_LEN) {
+        return Ok(Response::Err(Error::IdInvalid));
+    }
+    // only threads can stick
+    if matches!(action, Moderation::Stick(_) | Moderation::Unstick(_)) && id.len() != ID_LEN {
+        return Ok(Response::Err(Error::IdInvalid));
+    }
+    if matches!(action, Moderation::Stick(_) | Moderation::Unstick(_)) && archive::contains(id)? {
+        return Ok(Response::Err(Error::Archived));
+    }
+    let tree = match action {
+        Moderation::Approve(_) | Moderation::Discard(_) => HELD,
+        _ => CONTENT_TREE,
+    };
+    if !DB.open_tree(tree)?.contains_key(id)? {
+        return Ok(Response::Err(Error::NodeNotExist));
+    }
+    let log_id = encode_id(next_id());
+    let failed = match &action {
+        Moderation::Check | Moderation::Held => unreachable!(),
+        Moderation::Delete(id) => delete_tree(id)?,
+        Moderation::Lock(id) => {
+            DB.open_tree(LOCKS)?.insert(&id[..ID_LEN], &log_id)?;
+            None
+        }
+        Moderation::Unlock(id) => {
+            unlock(id)?;
+            None
+        }
+        Moderation::BanTripcode(id) => ban(id, false, &log_id)?,
+        Moderation::BanAddress(id) => ban(id, true, &log_id)?,
+        Moderation::Stick(id) => change_body(id, None, |body| body.sticky = true)?,
+        Moderation::Unstick(id) => change_body(id, None, |body| body.sticky = false)?,
+        Moderation::Approve(id) => approve(id)?,
+        Moderation::Discard(id) => discard(id)?,
+        Moderation::Restore(id) => tombstone::restore(id)?,
+    };
+    if let Some(e) = failed {
+        return Ok(Response::Err(e));
+    }
+    let record = Record {
+        time: Utc::now(),
+        name,
+        key,
+        action: action.clone(),
+        reason,
+    };
+    DB.open_tree(MOD_LOG)?.insert(log_id, bincode::serialize(&record)?)?;
+    log::info!("[moderate] {}.", record);
+    Ok(Response::Moderate(action))
+}
+
+/// last records of the log, oldest first.
+pub(crate) fn mod_log(last: usize) -> anyhow::Result<Vec<Record>> {
+    let mut records = DB
+        .open_tree(MOD_LOG)?
+        .iter()
+        .values()
+        .rev()
+        .take(last)
+        .map(|record| Ok(bincode::deserialize(&record?)?))
+        .collect::<anyhow::Result<Vec<Record>>>()?;
+    records.reverse();
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{address_ban, key_of, tripcode_ban};
+    use terminus_types::Author;
+
+    #[test]
+    fn ban_keys() {
+        let me = Author::new("Me!".to_string(), "ME!ME!ME!".to_string());
+        let key = key_of(me.clone());
+        let mut masked = me;
+        masked.mask();
+        // what posts carry is what gets banned
+        assert_eq!(masked.pass.get_pass(), key);
+        assert_ne!(tripcode_ban(&key), address_ban(&"127.0.0.1".parse().unwrap()));
+        assert_eq!(b"address:::1".to_vec(), address_ban(&"::1".parse().unwrap()));
+    }
+}
@@ -0,0 +1,140 @@
+//! threads in list order, so a page is a range scan from its cursor.
+
+use super::{archive, NodeBody, CONTENT_TREE, DB, ROOT_LIST};
+use chrono::{DateTime, Utc};
+use sled::{Batch, IVec, Tree};
+use std::collections::BTreeSet;
+use terminus_types::{action::Cursor, ID_LEN};
+
+/// sticky, last reply and id of live threads, to nothing.
+const ROOT_ORDER: &str = "root_order";
+/// last reply and id of archived threads, to nothing.
+const ARCHIVE_ORDER: &str = "archive_order";
+const TIME_LEN: usize = 12;
+pub(super) const ROOT_KEY_LEN: usize = 1 + TIME_LEN + ID_LEN;
+pub(super) const ARCHIVE_KEY_LEN: usize = TIME_LEN + ID_LEN;
+
+/// time that sorts as its bytes do.
+fn time_key(time: &DateTime<Utc>) -> [u8; TIME_LEN] {
+    let mut key = [0; TIME_LEN];
+    key[..8].copy_from_slice(&((time.timestamp() as u64) ^ (1 << 63)).to_be_bytes());
+    key[8..].copy_from_slice(&time.timestamp_subsec_nanos().to_be_bytes());
+    key
+}
+
+/// sticky first, then by last reply, when read backwards.
+pub(super) fn root_key(id: &[u8], body: &NodeBody) -> Vec<u8> {
+    let mut key = vec![body.sticky as u8];
+    key.extend_from_slice(&time_key(&body.last_reply));
+    key.extend_from_slice(id);
+    key
+}
+
+/// by last reply, which never changes once archived.
+pub(super) fn archive_key(id: &[u8], body: &NodeBody) -> Vec<u8> {
+    let mut key = time_key(&body.last_reply).to_vec();
+    key.extend_from_slice(id);
+    key
+}
+
+/// id of the thread a key orders.
+pub(super) fn id_of(key: &[u8]) -> &[u8] {
+    &key[key.len() - ID_LEN..]
+}
+
+/// keys of threads not sticky in root, least recently bumped first.
+pub(super) fn unstuck(root_order: &Tree) -> impl Iterator<Item = sled::Result<IVec>> {
+    root_order.scan_prefix([false as u8]).keys()
+}
+
+pub(super) fn root_tree() -> sled::Result<Tree> {
+    DB.open_tree(ROOT_ORDER)
+}
+
+pub(super) fn archive_tree() -> sled::Result<Tree> {
+    DB.open_tree(ARCHIVE_ORDER)
+}
+
+/// keys of a page newest first, from after cursor, and cursor of the next page if more left.
+/// none if cursor is not a key of this order.
+pub(super) fn page(
+    order: &Tree,
+    key_len: usize,
+    limit: usize,
+    cursor: Option<Cursor>,
+) -> anyhow::Result<Option<(Vec<IVec>, Option<Cursor>)>> {
+    let range = match cursor {
+        Some(ref cursor) if cursor.as_bytes().len() != key_len => return Ok(None),
+        Some(cursor) => order.range(..cursor.as_bytes().to_vec()),
+        None => order.iter(),
+    };
+    // one more to know if there is a next page
+    let mut keys = range
+        .keys()
+        .rev()
+        .take(limit.saturating_add(1))
+        .collect::<sled::Result<Vec<_>>>()?;
+    if keys.len() <= limit {
+        return Ok(Some((keys, None)));
+    }
+    keys.truncate(limit);
+    let next = keys.last().map(|key| Cursor::new(key.to_vec()));
+    Ok(Some((keys, next)))
+}
+
+/// keys out of step with root list and archive, put right unless dry run.
+/// gives ids of threads whose keys were wrong.
+pub(super) fn check(dry_run: bool) -> anyhow::Result<Vec<IVec>> {
+    let content = DB.open_tree(CONTENT_TREE)?;
+    let mut root = BTreeSet::new();
+    for item in DB.open_tree(ROOT_LIST)?.iter() {
+        let (id, body) = item?;
+        root.insert(IVec::from(root_key(&id, &NodeBody::decode(&body)?)));
+    }
+    let mut archived = BTreeSet::new();
+    for id in archive::tree()?.iter().keys() {
+        let id = id?;
+        if let Some(body) = content.get(&id)? {
+            archived.insert(IVec::from(archive_key(&id, &NodeBody::decode(&body)?)));
+        }
+    }
+    let mut wrong = BTreeSet::new();
+    for (tree, expected) in [(root_tree()?, root), (archive_tree()?, archived)] {
+        let mut batch = Batch::default();
+        for key in tree.iter().keys() {
+            let key = key?;
+            if !expected.contains(&key) {
+                wrong.insert(IVec::from(id_of(&key)));
+                batch.remove(key);
+            }
+        }
+        for key in expected {
+            if !tree.contains_key(&key)? {
+                wrong.insert(IVec::from(id_of(&key)));
+                batch.insert(key, &[]);
+            }
+        }
+        if !dry_run {
+            tree.apply_batch(batch)?;
+        }
+    }
+    Ok(wrong.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::time_key;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn time_sorts_as_bytes() {
+        let times = [
+            Utc.timestamp_opt(-1, 999_999_999).unwrap(),
+            Utc.timestamp_opt(0, 0).unwrap(),
+            Utc.timestamp_opt(0, 1).unwrap(),
+            Utc.timestamp_opt(1_600_000_000, 0).unwrap(),
+            Utc.timestamp_opt(1_600_000_001, 0).unwrap(),
+        ];
+        assert!(times.windows(2).all(|pair| time_key(&pair[0]) < time_key(&pair[1])));
+    }
+}
@@ -0,0 +1,305 @@
+//! versions of what is on disk, and the steps between them.
+
+use super::{moderate, NodeBody, CONTENT_TREE, DB, ROOT_LIST};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sled::{transaction::Transactional, IVec};
+use std::{convert::TryInto, fmt};
+use terminus_types::{encode_id, Author, ID_LEN};
+
+/// leads every stored node body, bumped when its layout changes.
+const BODY_VERSION: u8 = 3;
+
+/// node body of version 1, before sticky.
+#[derive(Deserialize, Serialize)]
+struct BodyV1 {
+    title: String,
+    author: Author,
+    content: String,
+    publish_time: DateTime<Utc>,
+    last_reply: DateTime<Utc>,
+    edited: bool,
+}
+
+/// node body of version 2, before deleted.
+#[derive(Deserialize, Serialize)]
+struct BodyV2 {
+    title: String,
+    author: Author,
+    content: String,
+    publish_time: DateTime<Utc>,
+    last_reply: DateTime<Utc>,
+    edited: bool,
+    sticky: bool,
+}
+
+impl NodeBody {
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut bin = vec![BODY_VERSION];
+        bincode::serialize_into(&mut bin, self).expect("should always serialize success");
+        bin
+    }
+
+    pub(crate) fn decode(bin: &[u8]) -> anyhow::Result<Self> {
+        match bin.split_first() {
+            Some((&BODY_VERSION, body)) => Ok(bincode::deserialize(body)?),
+            Some((version, _)) => anyhow::bail!("node body version {} unknown, migrate first", version),
+            None => anyhow::bail!("node body empty"),
+        }
+    }
+}
+
+/// a record changed by a step, none if left as it is.
+pub(super) type Rewrite = anyhow::Result<Option<(Vec<u8>, Vec<u8>)>>;
+
+/// one step from its index to the next version, on both trees.
+struct Step {
+    about: &'static str,
+    rewrite: fn(&[u8], &[u8]) -> Rewrite,
+}
+
+const STEPS: &[Step] = &[
+    Step {
+        about: "encode node ids big endian",
+        rewrite: reorder_key,
+    },
+    Step {
+        about: "add version header to node bodies",
+        rewrite: add_header,
+    },
+    Step {
+        about: "add sticky flag to node bodies",
+        rewrite: add_sticky,
+    },
+    Step {
+        about: "add deleted flag to node bodies",
+        rewrite: add_deleted,
+    },
+];
+
+/// version of a database all steps are done.
+pub(crate) const SCHEMA_VERSION: u32 = STEPS.len() as u32;
+const SCHEMA_KEY: &[u8] = b"schema_version";
+
+/// every layer of a little endian key in big endian.
+fn reorder_key(key: &[u8], value: &[u8]) -> Rewrite {
+    if key.is_empty() || !key.len().is_multiple_of(ID_LEN) {
+        log::warn!("[migrate] key {:?} is not an id, skipped.", key);
+        return Ok(None);
+    }
+    let mut res = Vec::with_capacity(key.len());
+    for layer in key.chunks(ID_LEN) {
+        res.extend_from_slice(&encode_id(u128::from_le_bytes(layer.try_into()?)));
+    }
+    Ok(Some((res, value.to_vec())))
+}
+
+fn add_header(key: &[u8], value: &[u8]) -> Rewrite {
+    let body: BodyV1 = bincode::deserialize(value)?;
+    let mut bin = vec![1];
+    bincode::serialize_into(&mut bin, &body)?;
+    Ok(Some((key.to_vec(), bin)))
+}
+
+fn add_sticky(key: &[u8], value: &[u8]) -> Rewrite {
+    let body: BodyV1 = match value.split_first() {
+        Some((1, body)) => bincode::deserialize(body)?,
+        _ => anyhow::bail!("node body {:?} is not version 1", key),
+    };
+    let BodyV1 {
+        title,
+        author,
+        content,
+        publish_time,
+        last_reply,
+        edited,
+    } = body;
+    let body = BodyV2 {
+        title,
+        author,
+        content,
+        publish_time,
+        last_reply,
+        edited,
+        sticky: false,
+    };
+    let mut bin = vec![2];
+    bincode::serialize_into(&mut bin, &body)?;
+    Ok(Some((key.to_vec(), bin)))
+}
+
+fn add_deleted(key: &[u8], value: &[u8]) -> Rewrite {
+    let body: BodyV2 = match value.split_first() {
+        Some((2, body)) => bincode::deserialize(body)?,
+        _ => anyhow::bail!("node body {:?} is not version 2", key),
+    };
+    let BodyV2 {
+        title,
+        author,
+        content,
+        publish_time,
+        last_reply,
+        edited,
+        sticky,
+    } = body;
+    let body = NodeBody {
+        title,
+        author,
+        content,
+        publish_time,
+        last_reply,
+        edited,
+        sticky,
+        deleted: false,
+    };
+    Ok(Some((key.to_vec(), body.encode())))
+}
+
+/// version of the database, a new one is stamped latest.
+pub(crate) fn schema_version() -> anyhow::Result<u32> {
+    if let Some(version) = DB.get(SCHEMA_KEY)? {
+        return Ok(u32::from_be_bytes(version.as_ref().try_into()?));
+    }
+    if DB.open_tree(CONTENT_TREE)?.is_empty() && DB.open_tree(ROOT_LIST)?.is_empty() {
+        DB.insert(SCHEMA_KEY, &SCHEMA_VERSION.to_be_bytes())?;
+        return Ok(SCHEMA_VERSION);
+    }
+    Ok(0)
+}
+
+/// refuse to serve a database of other version.
+pub(crate) fn check_schema() -> anyhow::Result<()> {
+    let version = schema_version()?;
+    if version < SCHEMA_VERSION {
+        anyhow::bail!(
+            "database is version {}, run `terminus-server migrate` to upgrade to {}",
+            version,
+            SCHEMA_VERSION
+        );
+    }
+    if version > SCHEMA_VERSION {
+        anyhow::bail!("database is version {}, newer than this server", version);
+    }
+    Ok(())
+}
+
+/// what migrate did, or would do on dry run.
+pub(crate) struct Report {
+    pub from: u32,
+    /// about and records changed of each step taken.
+    pub steps: Vec<(&'static str, usize)>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "database version {}, latest {}.", self.from, SCHEMA_VERSION)?;
+        for (ind, (about, changed)) in self.steps.iter().enumerate() {
+            let version = self.from as usize + ind;
+            writeln!(f, "  {} -> {}: {}, {} records.", version, version + 1, about, changed)?;
+        }
+        Ok(())
+    }
+}
+
+/// run all steps after current version in memory, then write them in one transaction.
+pub(crate) fn migrate(dry_run: bool) -> anyhow::Result<Report> {
+    let from = schema_version()?;
+    let mut report = Report { from, steps: Vec::new() };
+    let pending = STEPS.get(from as usize..).unwrap_or_default();
+    if pending.is_empty() {
+        return Ok(report);
+    }
+    let content = DB.open_tree(CONTENT_TREE)?;
+    let root_list = DB.open_tree(ROOT_LIST)?;
+    let held = moderate::held_tree()?;
+    let mut old_keys: Vec<Vec<IVec>> = Vec::new();
+    let mut records = Vec::new();
+    for tree in [&content, &root_list, &held] {
+        let all = tree.iter().collect::<sled::Result<Vec<_>>>()?;
+        old_keys.push(all.iter().map(|(key, _)| key.clone()).collect());
+        records.push(all.into_iter().map(|(k, v)| (k.to_vec(), v.to_vec())).collect::<Vec<_>>());
+    }
+    for step in pending {
+        let mut changed = 0;
+        for (ind, tree) in records.iter_mut().enumerate() {
+            for (key, value) in tree.iter_mut() {
+                // held posts carry a body inside
+                let rewritten = if ind == 2 {
+                    moderate::rewrite_held(key, value, step.rewrite)?
+                } else {
+                    (step.rewrite)(key, value)?
+                };
+                if let Some((new_key, new_value)) = rewritten {
+                    *key = new_key;
+                    *value = new_value;
+                    changed += 1;
+                }
+            }
+        }
+        report.steps.push((step.about, changed));
+    }
+    if dry_run {
+        return Ok(report);
+    }
+    (&content, &root_list, &held, &**DB)
+        .transaction(|(content, root_list, held, meta)| {
+            for ((tree, old_keys), records) in [content, root_list, held].iter().zip(&old_keys).zip(&records) {
+                // remove first, a new key may be an old one of another node
+                for key in old_keys {
+                    tree.remove(key)?;
+                }
+                for (key, value) in records {
+                    tree.insert(key.as_slice(), value.as_slice())?;
+                }
+            }
+            meta.insert(SCHEMA_KEY, &SCHEMA_VERSION.to_be_bytes())?;
+            Ok(())
+        })
+        .map_err(|e: sled::transaction::TransactionError<()>| anyhow::anyhow!("migration failed: {:?}", e))?;
+    DB.flush()?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{add_deleted, add_header, add_sticky, reorder_key, BodyV1, NodeBody};
+    use chrono::Utc;
+    use terminus_types::Author;
+
+    #[test]
+    fn reorder() {
+        let (old, new) = ((1u128 << 64) + 9, 1u128 << 64);
+        let mut key = bincode::serialize(&old).unwrap();
+        key.extend(bincode::serialize(&new).unwrap());
+        let (key, _) = reorder_key(&key, &[]).unwrap().unwrap();
+        assert_eq!(&key[..16], &old.to_be_bytes());
+        assert_eq!(&key[16..], &new.to_be_bytes());
+        assert!(reorder_key(&key[1..], &[]).unwrap().is_none());
+    }
+
+    #[test]
+    fn body_steps() {
+        let me = Author::new("Me!".to_string(), "ME!ME!ME!".to_string());
+        let now = Utc::now();
+        let body = BodyV1 {
+            title: "Hi".to_string(),
+            author: me,
+            content: "nothing".to_string(),
+            publish_time: now,
+            last_reply: now,
+            edited: true,
+        };
+        let bare = bincode::serialize(&body).unwrap();
+        let (_, v1) = add_header(&[], &bare).unwrap().unwrap();
+        assert_eq!(&bare, &v1[1..]);
+        assert!(NodeBody::decode(&v1).is_err());
+        let (_, v2) = add_sticky(&[], &v1).unwrap().unwrap();
+        assert!(NodeBody::decode(&v2).is_err());
+        let (_, v3) = add_deleted(&[], &v2).unwrap().unwrap();
+        let body = NodeBody::decode(&v3).unwrap();
+        assert!(body.edited && !body.sticky && !body.deleted);
+        assert_eq!(now, body.last_reply);
+        assert!(add_sticky(&[], &bare).is_err());
+        assert!(add_deleted(&[], &v1).is_err());
+    }
+}
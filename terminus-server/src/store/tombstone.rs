@@ -0,0 +1,186 @@
+//! deleted nodes stay as tombstones, what they were is kept a while for restore.
+
+use super::{abort, aggregate, history, moderate, order, read_body, relist, NodeBody, CONTENT_TREE, DB, ROOT_LIST};
+use crate::config;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sled::{
+    transaction::{ConflictableTransactionResult, TransactionError, Transactional, TransactionalTree},
+    Tree,
+};
+use std::mem;
+use terminus_types::{Author, Error, Pass, ID_LEN};
+
+/// node id to what was cleared from it.
+const DELETED: &str = "deleted";
+
+/// fields a tombstone lost, and when.
+#[derive(Serialize, Deserialize)]
+pub(super) struct Buried {
+    time: DateTime<Utc>,
+    title: String,
+    author: Author,
+    content: String,
+}
+
+/// clear body into a tombstone, what it was is given back.
+pub(super) fn bury(body: &mut NodeBody, time: DateTime<Utc>) -> Buried {
+    let nobody = Author {
+        name: String::new(),
+        pass: Pass::Mask(String::new()),
+    };
+    body.deleted = true;
+    Buried {
+        time,
+        title: mem::take(&mut body.title),
+        author: mem::replace(&mut body.author, nobody),
+        content: mem::take(&mut body.content),
+    }
+}
+
+impl Buried {
+    pub(super) fn author(&self) -> &Author {
+        &self.author
+    }
+}
+
+/// what was buried, kept in the transaction that buries it.
+pub(super) fn tree() -> sled::Result<Tree> {
+    DB.open_tree(DELETED)
+}
+
+/// keep what was buried, until retention is over.
+pub(super) fn keep(
+    deleted: &TransactionalTree,
+    id: &[u8],
+    buried: &Buried,
+) -> ConflictableTransactionResult<(), Error> {
+    deleted.insert(id, bincode::serialize(buried).expect("should always serialize success"))?;
+    Ok(())
+}
+
+/// author before delete, for bans.
+pub(super) fn author_of(id: &[u8]) -> anyhow::Result<Option<Author>> {
+    match DB.open_tree(DELETED)?.get(id)? {
+        Some(buried) => Ok(Some(bincode::deserialize::<Buried>(&buried)?.author)),
+        None => Ok(None),
+    }
+}
+
+/// bring back node and nodes under it deleted at the same time.
+pub(super) fn restore(id: &[u8]) -> anyhow::Result<Option<Error>> {
+    let deleted = DB.open_tree(DELETED)?;
+    let time = match deleted.get(id)? {
+        Some(buried) => bincode::deserialize::<Buried>(&buried)?.time,
+        None => return Ok(Some(Error::NodeNotExist)),
+    };
+    if Utc::now() - time > config::current().retention() {
+        return Ok(Some(Error::NodeNotExist));
+    }
+    let mut together = Vec::new();
+    for item in deleted.scan_prefix(id) {
+        let (key, buried) = item?;
+        let buried: Buried = bincode::deserialize(&buried)?;
+        if buried.time == time {
+            together.push((key, buried));
+        }
+    }
+    let content = DB.open_tree(CONTENT_TREE)?;
+    let root_list = DB.open_tree(ROOT_LIST)?;
+    let root_order = order::root_tree()?;
+    let aggregates = aggregate::tree()?;
+    let trees = (&content, &root_list, &root_order, &aggregates, &deleted);
+    let res = trees.transaction(|(content, root_list, root_order, aggregates, deleted)| {
+        let mut alive = Vec::new();
+        for (key, buried) in together.iter() {
+            let mut body = match content.get(key)? {
+                Some(body) => read_body(&body)?,
+                // purged meanwhile
+                None => return abort(Error::NodeNotExist),
+            };
+            body.title = buried.title.clone();
+            body.author = buried.author.clone();
+            body.content = buried.content.clone();
+            body.deleted = false;
+            content.insert(key, body.clone())?;
+            relist(root_list, root_order, key, body)?;
+            deleted.remove(key)?;
+            alive.push((key.as_ref(), &buried.author));
+        }
+        aggregate::add(aggregates, &id[..ID_LEN], &alive)?;
+        Ok(())
+    });
+    match res {
+        Ok(()) => Ok(None),
+        Err(TransactionError::Abort(e)) => Ok(Some(e)),
+        Err(TransactionError::Storage(e)) => Err(e.into()),
+    }
+}
+
+/// forget what is past retention, and remove tombstones no reply needs.
+/// gives how many nodes are removed.
+pub(super) fn purge_once() -> anyhow::Result<usize> {
+    let retention = config::current().retention();
+    let now = Utc::now();
+    let deleted = DB.open_tree(DELETED)?;
+    let history = history::tree()?;
+    for item in deleted.iter() {
+        let (key, buried) = item?;
+        if now - bincode::deserialize::<Buried>(&buried)?.time <= retention {
+            continue;
+        }
+        let revisions = history::keys_of(&key)?;
+        let res = (&deleted, &history).transaction(|(deleted, history)| {
+            // restored meanwhile
+            if deleted.remove(&key)?.is_none() {
+                return abort(Error::NodeNotExist);
+            }
+            for revision in revisions.iter() {
+                history.remove(revision)?;
+            }
+            Ok(())
+        });
+        if let Err(TransactionError::Storage(e)) = res {
+            return Err(e.into());
+        }
+    }
+    let content = DB.open_tree(CONTENT_TREE)?;
+    let root_list = DB.open_tree(ROOT_LIST)?;
+    let root_order = order::root_tree()?;
+    let aggregates = aggregate::tree()?;
+    let sources = moderate::sources()?;
+    let mut removed = 0;
+    // replies go first, so their parents may go after
+    for item in content.iter().rev() {
+        let (key, body) = item?;
+        if !NodeBody::decode(&body)?.deleted || deleted.contains_key(&key)? {
+            continue;
+        }
+        // tombstones take no replies, so none come meanwhile
+        if content.scan_prefix(&key).keys().nth(1).is_some() {
+            continue;
+        }
+        let trees = (&content, &root_list, &root_order, &aggregates, &sources);
+        let res = trees.transaction(|(content, root_list, root_order, aggregates, sources)| {
+            match content.remove(&key)? {
+                Some(body) if read_body(&body)?.deleted => {}
+                // restored meanwhile
+                _ => return abort(Error::NodeNotExist),
+            }
+            if key.len() == ID_LEN {
+                if let Some(body) = root_list.remove(&key)? {
+                    root_order.remove(order::root_key(&key, &read_body(&body)?))?;
+                }
+                aggregates.remove(&key)?;
+            }
+            sources.remove(&key)?;
+            Ok(())
+        });
+        match res {
+            Ok(()) => removed += 1,
+            Err(TransactionError::Abort(_)) => {}
+            Err(TransactionError::Storage(e)) => return Err(e.into()),
+        }
+    }
+    Ok(removed)
+}
@@ -0,0 +1,37 @@
+use std::{fs::File, io::BufReader, path::Path, sync::Arc};
+use tokio_rustls::{
+    rustls::{
+        internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys},
+        NoClientAuth, PrivateKey, ServerConfig,
+    },
+    TlsAcceptor,
+};
+
+fn load_key(path: &Path) -> anyhow::Result<PrivateKey> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut keys = pkcs8_private_keys(&mut reader).map_err(|_| anyhow::anyhow!("bad pkcs8 key file"))?;
+    if keys.is_empty() {
+        let mut reader = BufReader::new(File::open(path)?);
+        keys = rsa_private_keys(&mut reader).map_err(|_| anyhow::anyhow!("bad rsa key file"))?;
+    }
+    keys.pop()
+        .ok_or_else(|| anyhow::anyhow!("no private key found in {}", path.display()))
+}
+
+/// build a tls acceptor from pem encoded cert chain and key.
+pub(crate) fn acceptor(cert: &Path, key: &Path) -> anyhow::Result<TlsAcceptor> {
+    let mut reader = BufReader::new(File::open(cert)?);
+    let cert_chain = certs(&mut reader).map_err(|_| anyhow::anyhow!("bad cert file"))?;
+    if let Some(leaf) = cert_chain.first() {
+        log::info!(
+            "certificate fingerprint: {}.",
+            terminus_types::cert_fingerprint(&leaf.0)
+        );
+    } else {
+        anyhow::bail!("no certificate found in {}", cert.display());
+    }
+    let key = load_key(key)?;
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.set_single_cert(cert_chain, key)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
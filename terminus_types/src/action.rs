@@ -1,25 +1,276 @@
-use crate::{Error, Node, NodeId};
+use crate::{stamp::Difficulty, Author, Draft, Error, Limits, Node, NodeId, Patch, Result, Revision, ThreadStats};
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+/// current protocol version.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// oldest peer version we can still talk to.
+/// new data comes as new variants at the end, only raise it when a layout has to break.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+/// leads every [`Hello`] frame, never a valid [`Action`] variant.
+/// peers without it are taken as version 0, which never say hello.
+const HELLO_MAGIC: u32 = u32::from_be_bytes(*b"TRMS");
+
+/// optional features, announced in [`Hello`].
+pub mod capability {
+    /// server push node changes.
+    pub const NOTIFY: &str = "notify";
+    /// frames are wrapped in [`super::Envelope`].
+    pub const ENVELOPE: &str = "envelope";
+    /// only push changes of subscribed targets, instead of all of them.
+    pub const SUBSCRIBE: &str = "subscribe";
+}
+
+/// first frame on both side of a link.
+/// layout must never change, so any version can read it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Hello {
+    magic: u32,
+    pub version: u32,
+    pub min_version: u32,
+    pub name: String,
+    pub capabilities: Vec<String>,
+}
+
+impl Hello {
+    pub fn new<T: AsRef<str>>(name: T, capabilities: &[&str]) -> Self {
+        Self {
+            magic: HELLO_MAGIC,
+            version: PROTOCOL_VERSION,
+            min_version: MIN_PROTOCOL_VERSION,
+            name: name.as_ref().to_owned(),
+            capabilities: capabilities.iter().map(|cap| cap.to_string()).collect(),
+        }
+    }
+
+    /// check if a frame is a hello, before deserialize it.
+    pub fn is_hello(frame: &[u8]) -> bool {
+        frame.get(..4) == Some(&HELLO_MAGIC.to_le_bytes())
+    }
+
+    pub fn supports(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|cap| cap == capability)
+    }
+
+    /// check if we can talk to server who send this hello.
+    pub fn check_server(&self) -> Result<()> {
+        if self.version < MIN_PROTOCOL_VERSION {
+            return Err(Error::ServerTooOld);
+        }
+        if PROTOCOL_VERSION < self.min_version {
+            return Err(Error::ServerTooNew);
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if both side understand each other.
+    pub fn is_compatible(&self) -> bool {
+        self.check_server().is_ok()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub enum ListTarget {
     Root,
     Node(NodeId),
+    /// threads moved off root, read only.
+    Archive,
+}
+
+/// position in a listing, given by server.
+/// opaque to client, just send it back for next page.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Cursor(Vec<u8>);
+
+impl Cursor {
+    pub fn new(inner: Vec<u8>) -> Self {
+        Self(inner)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Action {
     Delete(Node),
-    List(ListTarget),
-    Update(Node),
-    Post(Node),
+    /// list at most limit nodes, after cursor if any.
+    List {
+        target: ListTarget,
+        limit: u32,
+        cursor: Option<Cursor>,
+    },
+    /// only changed fields, the server keeps times and author.
+    Update(Patch),
+    /// server gives the id, and replies the node posted.
+    Post(Draft),
+    /// push changes of target to this link.
+    Subscribe(ListTarget),
+    Unsubscribe(ListTarget),
+    /// moderators only, pass must be unmasked. reason goes to the log.
+    Moderate {
+        author: Author,
+        action: Moderation,
+        reason: String,
+    },
+    /// what node said before its edits.
+    History(NodeId),
+    /// as post, but a reply that does not bump its thread.
+    Sage(Draft),
+}
+
+/// what a moderator can do, every one but check is logged.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Moderation {
+    /// succeeds only if author is a moderator.
+    Check,
+    /// remove any node, with its replies.
+    Delete(NodeId),
+    /// no more replies to the thread of node.
+    Lock(NodeId),
+    Unlock(NodeId),
+    /// ban the tripcode of who posted node.
+    BanTripcode(NodeId),
+    /// ban the address node was posted from.
+    BanAddress(NodeId),
+    /// keep a thread on top of root.
+    Stick(NodeId),
+    Unstick(NodeId),
+    /// posts and edits held by filter, not logged.
+    Held,
+    /// put a held post or edit on board.
+    Approve(NodeId),
+    /// drop a held post or edit.
+    Discard(NodeId),
+    /// bring back a deleted node, and replies deleted with it.
+    Restore(NodeId),
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Response {
     Post(Node),
     Update(Node),
+    /// the tombstone left, or only the id of a node gone for good.
     Delete(Node),
-    List(Vec<Node>),
+    /// a page of target, next is none on last page.
+    /// pages of root and archive are led by stats of their threads.
+    List {
+        target: ListTarget,
+        nodes: Vec<Node>,
+        next: Option<Cursor>,
+    },
     Err(Error),
+    Subscribe(ListTarget),
+    Unsubscribe(ListTarget),
+    /// the moderation done.
+    Moderate(Moderation),
+    /// board wide announcement, pushed on link and when changed.
+    Announcement(Option<String>),
+    /// limits of nodes, pushed on link and when changed.
+    Limits(Limits),
+    /// proof of work posts need, and the salt stamps are made with.
+    /// pushed on link and when either changes.
+    Difficulty(Difficulty),
+    /// posts and edits waiting for moderators, with why they are held.
+    /// edits come as the node would be after them.
+    Held(Vec<(Node, String)>),
+    /// revisions of node before its edits, oldest first.
+    History { id: NodeId, revisions: Vec<Revision> },
+    /// stats of threads, pushed ahead of a page of root or archive,
+    /// and to root subscribers as they change.
+    Stats(Vec<(NodeId, ThreadStats)>),
+}
+
+pub type RequestId = u64;
+
+/// wrap actions and responses, so replies can be matched to requests.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Envelope {
+    /// from client.
+    Request { id: RequestId, action: Action },
+    /// reply to the request with same id.
+    Reply { id: RequestId, response: Response },
+    /// pushed by server, not a reply to any request.
+    Event(Response),
+}
+
+impl Envelope {
+    /// id of a request frame, even if the action in it can not be read.
+    pub fn peek_id(frame: &[u8]) -> Option<RequestId> {
+        // variant index, then id
+        let (variant, id): (u32, RequestId) = bincode::deserialize(frame).ok()?;
+        if variant == 0 {
+            Some(id)
+        } else {
+            None
+        }
+    }
+
+    /// unwrap for peers without envelope.
+    pub fn into_response(self) -> Option<Response> {
+        match self {
+            Envelope::Reply { response, .. } | Envelope::Event(response) => Some(response),
+            Envelope::Request { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{capability, Action, Envelope, Hello, ListTarget, Response};
+    use crate::Error;
+
+    #[test]
+    fn hello_detect() {
+        let hello = bincode::serialize(&Hello::new("board", &[capability::NOTIFY])).unwrap();
+        assert!(Hello::is_hello(&hello));
+        let action = Action::List {
+            target: ListTarget::Root,
+            limit: 1,
+            cursor: None,
+        };
+        let action = bincode::serialize(&action).unwrap();
+        assert!(!Hello::is_hello(&action));
+    }
+
+    #[test]
+    fn hello_compatible() {
+        let mut hello = Hello::new("board", &[]);
+        assert!(hello.is_compatible());
+        hello.version = 0;
+        assert!(hello.check_server().is_err());
+        hello.version = u32::MAX;
+        hello.min_version = u32::MAX;
+        assert!(hello.check_server().is_err());
+    }
+
+    #[test]
+    fn envelope_unwrap() {
+        let reply = Envelope::Reply {
+            id: 1,
+            response: Response::Err(Error::NodeNotExist),
+        };
+        assert!(matches!(reply.into_response(), Some(Response::Err(_))));
+        let request = Envelope::Request {
+            id: 1,
+            action: Action::Subscribe(ListTarget::Root),
+        };
+        assert!(request.into_response().is_none());
+    }
+
+    #[test]
+    fn envelope_peek() {
+        let request = Envelope::Request {
+            id: 42,
+            action: Action::Subscribe(ListTarget::Root),
+        };
+        let mut frame = bincode::serialize(&request).unwrap();
+        assert_eq!(Some(42), Envelope::peek_id(&frame));
+        // broken action
+        frame.truncate(12);
+        assert!(bincode::deserialize::<Envelope>(&frame).is_err());
+        assert_eq!(Some(42), Envelope::peek_id(&frame));
+        let event = bincode::serialize(&Envelope::Event(Response::Err(Error::Internal))).unwrap();
+        assert_eq!(None, Envelope::peek_id(&event));
+    }
 }
@@ -0,0 +1,149 @@
+//! length delimited frames: u32 length, then bincode body.
+//! a zero length frame marks end of stream.
+
+use serde::{de::DeserializeOwned, Serialize};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// default max frame size, 16 MiB.
+pub const DEFAULT_MAX_FRAME: u32 = 16 << 20;
+// u32 0
+const EOS: [u8; 4] = [0; 4];
+
+#[derive(Error, Debug)]
+pub enum FrameError {
+    /// peer declared a frame over limit, stream can not go on.
+    #[error("frame of {0} bytes is over limit")]
+    TooLarge(u64),
+    /// frame body can not be decoded, stream is still usable.
+    #[error("frame malformed: {0}")]
+    Malformed(#[from] bincode::Error),
+    #[error("link failed: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl FrameError {
+    /// Returns `true` if the link is still in sync after this error.
+    pub fn is_recoverable(&self) -> bool {
+        matches!(self, Self::Malformed(_))
+    }
+}
+
+pub type FrameResult<T> = std::result::Result<T, FrameError>;
+
+pub struct FrameReader<R> {
+    inner: R,
+    buf: Vec<u8>,
+    max_frame: u32,
+}
+
+impl<R: AsyncRead + Unpin> FrameReader<R> {
+    pub fn new(inner: R, max_frame: u32) -> Self {
+        Self {
+            inner,
+            buf: Vec::new(),
+            max_frame,
+        }
+    }
+
+    /// next raw frame, `None` on end of stream.
+    pub async fn next_frame(&mut self) -> FrameResult<Option<&[u8]>> {
+        let mut indicator = [0u8; 4];
+        self.inner.read_exact(&mut indicator).await?;
+        let size = u32::from_le_bytes(indicator);
+        if size == 0 {
+            return Ok(None);
+        }
+        if size > self.max_frame {
+            return Err(FrameError::TooLarge(size as u64));
+        }
+        self.buf.resize(size as usize, 0u8);
+        self.inner.read_exact(&mut self.buf).await?;
+        Ok(Some(&self.buf))
+    }
+
+    /// next frame decoded, `None` on end of stream.
+    pub async fn read<T: DeserializeOwned>(&mut self) -> FrameResult<Option<T>> {
+        match self.next_frame().await? {
+            Some(frame) => Ok(Some(bincode::deserialize(frame)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+pub struct FrameWriter<W> {
+    inner: W,
+    max_frame: u32,
+}
+
+impl<W: AsyncWrite + Unpin> FrameWriter<W> {
+    pub fn new(inner: W, max_frame: u32) -> Self {
+        Self { inner, max_frame }
+    }
+
+    /// frames over limit are refused, before anything is written.
+    pub async fn write<T: Serialize>(&mut self, val: &T) -> FrameResult<()> {
+        let size = bincode::serialized_size(val)?;
+        if size == 0 {
+            // would be taken as end of stream
+            return Err(bincode::Error::from(bincode::ErrorKind::Custom("empty frame".to_owned())).into());
+        }
+        if size > self.max_frame as u64 {
+            return Err(FrameError::TooLarge(size));
+        }
+        let mut frame = Vec::with_capacity(size as usize + 4);
+        frame.extend_from_slice(&(size as u32).to_le_bytes());
+        bincode::serialize_into(&mut frame, val)?;
+        self.inner.write_all(&frame).await?;
+        Ok(())
+    }
+
+    /// send end of stream.
+    pub async fn finish(&mut self) -> FrameResult<()> {
+        self.inner.write_all(&EOS).await?;
+        self.inner.flush().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FrameError, FrameReader, FrameWriter};
+
+    #[tokio::test]
+    async fn round_trip() {
+        let mut buf = Vec::new();
+        let mut writer = FrameWriter::new(&mut buf, 64);
+        writer.write(&String::from("hello")).await.unwrap();
+        writer.finish().await.unwrap();
+        let mut reader = FrameReader::new(buf.as_slice(), 64);
+        assert_eq!(Some(String::from("hello")), reader.read().await.unwrap());
+        assert_eq!(None, reader.read::<String>().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn over_limit() {
+        let frame = u32::MAX.to_le_bytes();
+        let mut reader = FrameReader::new(&frame[..], 64);
+        assert!(matches!(reader.next_frame().await, Err(FrameError::TooLarge(_))));
+        let mut buf = Vec::new();
+        let mut writer = FrameWriter::new(&mut buf, 4);
+        assert!(matches!(
+            writer.write(&String::from("hello")).await,
+            Err(FrameError::TooLarge(_))
+        ));
+        assert!(buf.is_empty());
+    }
+
+    #[tokio::test]
+    async fn malformed() {
+        let mut buf = Vec::new();
+        let mut writer = FrameWriter::new(&mut buf, 64);
+        writer.write(&1u8).await.unwrap();
+        writer.write(&String::from("hello")).await.unwrap();
+        let mut reader = FrameReader::new(buf.as_slice(), 64);
+        let err = reader.read::<String>().await.unwrap_err();
+        assert!(err.is_recoverable());
+        assert_eq!(Some(String::from("hello")), reader.read().await.unwrap());
+    }
+}
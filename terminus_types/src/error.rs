@@ -17,6 +17,54 @@ pub enum Error {
     DeleteLimitOverdue,
     #[error("can not link to peer")]
     NetworkError,
+    #[error("server too old")]
+    ServerTooOld,
+    #[error("server too new")]
+    ServerTooNew,
+    #[error("list cursor not valid")]
+    CursorInvalid,
+    #[error("message too large")]
+    FrameTooLarge,
+    #[error("server internal error")]
+    Internal,
+    #[error("request malformed")]
+    Malformed,
+    #[error("request not supported")]
+    Unsupported,
+    #[error("nothing changed")]
+    NoChange,
+    #[error("node is too old to edit")]
+    EditLimitOverdue,
+    #[error("not a moderator")]
+    NotModerator,
+    #[error("thread is locked")]
+    Locked,
+    #[error("banned from posting")]
+    Banned,
+    #[error("source of node unknown")]
+    SourceUnknown,
+    #[error("posting too fast, retry after {retry_after} seconds")]
+    RateLimited { retry_after: u64 },
+    #[error("title is empty")]
+    TitleEmpty,
+    #[error("title too long")]
+    TitleTooLong,
+    #[error("content too long")]
+    ContentTooLong,
+    #[error("content has too many lines")]
+    TooManyLines,
+    #[error("reply nested too deep")]
+    TooDeep,
+    #[error("proof of work missing or too weak, {difficulty} bits needed")]
+    StampInvalid { difficulty: u32 },
+    #[error("rejected: {reason}")]
+    Rejected { reason: String },
+    #[error("held for moderation")]
+    Held,
+    #[error("thread is archived")]
+    Archived,
+    #[error("too many subscriptions on this link")]
+    TooManySubscriptions,
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
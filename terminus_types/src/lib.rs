@@ -1,22 +1,15 @@
 use action::Action;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::convert::TryInto;
 
 pub mod action;
+pub mod codec;
 mod error;
+pub mod stamp;
 
 pub use error::{Error, Result};
 
-// u128 is 8 * 16
-fn get_id(tail: u128) -> u128 {
-    let unix_timestamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("Hello John titor!")
-        .as_secs() as u128;
-    (unix_timestamp << 64) + tail
-}
-
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Pass {
     Pass(String),
@@ -79,6 +72,76 @@ impl Author {
 
 pub type NodeId = Vec<u8>;
 
+/// bytes of one id layer, a node id is its ancestors' layers then its own.
+pub const ID_LEN: usize = 16;
+
+/// one layer of node id, big endian so byte order is time order.
+pub fn encode_id(id: u128) -> [u8; ID_LEN] {
+    id.to_be_bytes()
+}
+
+pub fn decode_id(layer: &[u8]) -> Result<u128> {
+    let layer: [u8; ID_LEN] = layer.try_into().map_err(|_| Error::IdInvalid)?;
+    Ok(u128::from_be_bytes(layer))
+}
+
+/// most chars in a title by default.
+pub const TITLE_LIMIT: usize = 256;
+/// most chars in content by default.
+pub const CONTENT_LIMIT: usize = 16 * 1024;
+/// most lines in content by default.
+pub const LINE_LIMIT: usize = 500;
+/// deepest reply by default, threads are depth 1.
+pub const DEPTH_LIMIT: usize = 32;
+
+/// limits of a node, sizes in chars.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct Limits {
+    pub title: usize,
+    pub content: usize,
+    pub lines: usize,
+    pub depth: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            title: TITLE_LIMIT,
+            content: CONTENT_LIMIT,
+            lines: LINE_LIMIT,
+            depth: DEPTH_LIMIT,
+        }
+    }
+}
+
+impl Limits {
+    /// title should not be blank, all should be in limits.
+    pub fn check(&self, title: &str, content: &str, depth: usize) -> Result<()> {
+        if title.trim().is_empty() {
+            return Err(Error::TitleEmpty);
+        }
+        if title.chars().count() > self.title {
+            return Err(Error::TitleTooLong);
+        }
+        if content.chars().count() > self.content {
+            return Err(Error::ContentTooLong);
+        }
+        if content.lines().count() > self.lines {
+            return Err(Error::TooManyLines);
+        }
+        if depth > self.depth {
+            return Err(Error::TooDeep);
+        }
+        Ok(())
+    }
+}
+
+/// fingerprint of a DER encoded certificate, used for pinning.
+pub fn cert_fingerprint(der: &[u8]) -> String {
+    blake3::hash(der).to_hex().to_ascii_lowercase()
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Node {
     pub id: NodeId,
@@ -89,31 +152,45 @@ pub struct Node {
     // only update on top level node
     pub last_reply: DateTime<Utc>,
     pub edited: bool,
+    /// top level only, set by moderators to stay on top of root.
+    pub sticky: bool,
+    /// a tombstone, kept so replies keep their parent.
+    /// title, content and author are cleared.
+    pub deleted: bool,
 }
 
-fn mask_name_pass(name: &str, pass: &str) -> String {
-    let mut input = name.to_owned();
-    input.push_str(pass);
-    base64::encode(blake3::hash(input.as_bytes()).as_bytes())
+/// a node not posted yet, the server gives it id and time.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Draft {
+    pub parent: NodeId,
+    pub title: String,
+    pub author: Author,
+    pub content: String,
+    /// proof of work, see [`stamp`].
+    pub stamp: Option<u64>,
 }
 
-impl Node {
-    pub fn new(parent_id: &[u8], title: String, author: Author, content: String, tail: u64) -> Self {
-        let mut id = parent_id.to_owned();
-        let mut node_id = bincode::serialize(&get_id(tail as u128)).unwrap();
-        id.append(&mut node_id);
-        let now = Utc::now();
+impl Draft {
+    pub fn new(parent: &[u8], title: String, author: Author, content: String) -> Self {
         Self {
-            id,
+            parent: parent.to_owned(),
             title,
             author,
             content,
-            publish_time: now,
-            last_reply: now,
-            edited: false,
+            stamp: None,
         }
     }
 
+    /// what the stamp of this draft is made for, with salt.
+    pub fn challenge(&self, salt: &stamp::Salt) -> [u8; 32] {
+        stamp::challenge(salt, &self.parent, &self.title, &self.content)
+    }
+
+    /// work of the stamp made with salt, none if not stamped.
+    pub fn work(&self, salt: &stamp::Salt) -> Option<[u8; 32]> {
+        self.stamp.map(|nonce| stamp::work(&self.challenge(salt), nonce))
+    }
+
     pub fn post(mut self) -> Action {
         if !self.author.is_masked() {
             self.author.mask();
@@ -121,14 +198,111 @@ impl Node {
         Action::Post(self)
     }
 
-    pub fn update(mut self) -> Result<Action> {
+    /// as [`Self::post`], but a reply that does not bump its thread.
+    pub fn sage(mut self) -> Action {
+        if !self.author.is_masked() {
+            self.author.mask();
+        }
+        Action::Sage(self)
+    }
+
+    /// the node it becomes, id is the last part.
+    pub fn into_node(self, id: u128) -> Node {
+        let Draft {
+            parent,
+            title,
+            author,
+            content,
+            ..
+        } = self;
+        let mut node_id = parent;
+        node_id.extend_from_slice(&encode_id(id));
+        let now = Utc::now();
+        Node {
+            id: node_id,
+            title,
+            author,
+            content,
+            publish_time: now,
+            last_reply: now,
+            edited: false,
+            sticky: false,
+            deleted: false,
+        }
+    }
+}
+
+/// what a node said before an edit.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Revision {
+    pub title: String,
+    pub content: String,
+    /// when the edit replaced it.
+    pub replaced: DateTime<Utc>,
+}
+
+/// what is under a thread, kept by server so root can show it.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ThreadStats {
+    /// replies alive, tombstones left out.
+    pub replies: u64,
+    /// tripcodes posted in thread, its own included.
+    pub posters: u64,
+    /// author of the newest reply, none if no reply.
+    pub last_author: Option<Author>,
+}
+
+/// changes to a posted node, fields left none stay as they are.
+/// author is only used to check pass.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Patch {
+    pub id: NodeId,
+    pub author: Author,
+    pub title: Option<String>,
+    pub content: Option<String>,
+}
+
+impl Patch {
+    pub fn new(id: NodeId, author: Author) -> Self {
+        Self {
+            id,
+            author,
+            title: None,
+            content: None,
+        }
+    }
+
+    /// only fields differ from node are kept.
+    pub fn diff(node: &Node, title: String, content: String, author: Author) -> Self {
+        let mut patch = Self::new(node.id.clone(), author);
+        if title != node.title {
+            patch.title = Some(title);
+        }
+        if content != node.content {
+            patch.content = Some(content);
+        }
+        patch
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none() && self.content.is_none()
+    }
+
+    pub fn update(self) -> Result<Action> {
         if self.author.is_masked() {
             return Err(Error::NeedUnMaskPass);
         }
-        self.edited = true;
         Ok(Action::Update(self))
     }
+}
+
+fn mask_name_pass(name: &str, pass: &str) -> String {
+    let mut input = name.to_owned();
+    input.push_str(pass);
+    base64::encode(blake3::hash(input.as_bytes()).as_bytes())
+}
 
+impl Node {
     /// delete need to send pass and id, all other field is not needed.
     /// but for clean api, all them has same pattern. may change in the future.
     pub fn delete(self) -> Result<Action> {
@@ -138,26 +312,40 @@ impl Node {
         Ok(Action::Delete(self))
     }
 
+    /// id of the parent, empty for top level node.
+    pub fn parent_id(&self) -> &[u8] {
+        &self.id[..self.id.len().saturating_sub(ID_LEN)]
+    }
+
     /// the last part of id.
     pub fn last_id(&self) -> Result<u128> {
         // should always have one
-        let last = self.id.get(self.id.len() - 16..);
-        bincode::deserialize(last.ok_or(Error::IdInvalid)?).map_err(|_| Error::IdInvalid)
+        let start = self.id.len().checked_sub(ID_LEN).ok_or(Error::IdInvalid)?;
+        decode_id(&self.id[start..])
     }
 
     pub fn top_id(&self) -> Result<u128> {
         // should always have one
-        let last = self.id.get(..16);
-        bincode::deserialize(last.ok_or(Error::IdInvalid)?).map_err(|_| Error::IdInvalid)
+        decode_id(self.id.get(..ID_LEN).ok_or(Error::IdInvalid)?)
     }
 
     pub fn top_id_bin(&self) -> Result<Vec<u8>> {
         // should always have one
-        Ok(self.id.get(..16).ok_or(Error::IdInvalid)?.to_owned())
+        Ok(self.id.get(..ID_LEN).ok_or(Error::IdInvalid)?.to_owned())
     }
 
     pub fn is_top_level(&self) -> bool {
-        self.id.len() == 16
+        self.id.len() == ID_LEN
+    }
+
+    /// threads are depth 1, their replies 2, and so on.
+    pub fn depth(&self) -> usize {
+        self.id.len() / ID_LEN
+    }
+
+    /// check node against limits, before posting and after editing.
+    pub fn validate(&self, limits: &Limits) -> Result<()> {
+        limits.check(&self.title, &self.content, self.depth())
     }
 
     pub fn empty_node() -> Self {
@@ -172,13 +360,15 @@ impl Node {
             publish_time: Utc::now(),
             last_reply: Utc::now(),
             edited: false,
+            sticky: false,
+            deleted: false,
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Author;
+    use super::{decode_id, encode_id, Author, Draft, Error, Limits, Patch, CONTENT_LIMIT, LINE_LIMIT, TITLE_LIMIT};
 
     #[test]
     fn mask_unlock() {
@@ -188,4 +378,50 @@ mod tests {
         author.mask();
         assert!(author.match_pass(&name, &pass));
     }
+
+    #[test]
+    fn patch_diff() {
+        let me = Author::new("me".to_string(), "pass".to_string());
+        let node = super::Draft::new(&[], "t".to_string(), me.clone(), "c".to_string()).into_node(1);
+        let patch = Patch::diff(&node, "t".to_string(), "c".to_string(), me.clone());
+        assert!(patch.is_empty());
+        let patch = Patch::diff(&node, "t".to_string(), "d".to_string(), me);
+        assert_eq!(None, patch.title);
+        assert_eq!(Some("d"), patch.content.as_deref());
+    }
+
+    #[test]
+    fn limits() {
+        let limits = Limits::default();
+        assert!(limits.check(&"題".repeat(TITLE_LIMIT), &"c".repeat(CONTENT_LIMIT), 1).is_ok());
+        assert!(matches!(limits.check(&"題".repeat(TITLE_LIMIT + 1), "", 1), Err(Error::TitleTooLong)));
+        assert!(matches!(limits.check("t", &"c".repeat(CONTENT_LIMIT + 1), 1), Err(Error::ContentTooLong)));
+        assert!(matches!(limits.check(" \t", "", 1), Err(Error::TitleEmpty)));
+        assert!(matches!(limits.check("t", &"\n".repeat(LINE_LIMIT + 1), 1), Err(Error::TooManyLines)));
+    }
+
+    #[test]
+    fn validate_depth() {
+        let me = Author::new("me".to_string(), "pass".to_string());
+        let limits = Limits {
+            depth: 2,
+            ..Limits::default()
+        };
+        let top = Draft::new(&[], "t".to_string(), me.clone(), "c".to_string()).into_node(1);
+        let reply = Draft::new(&top.id, "t".to_string(), me.clone(), "c".to_string()).into_node(2);
+        assert!(reply.validate(&limits).is_ok());
+        let deeper = Draft::new(&reply.id, "t".to_string(), me, "c".to_string()).into_node(3);
+        assert!(matches!(deeper.validate(&limits), Err(Error::TooDeep)));
+    }
+
+    #[test]
+    fn id_order() {
+        let ids = [0u128, 255, 256, 1 << 64, (1 << 64) + 1, u128::MAX];
+        let encoded: Vec<_> = ids.iter().map(|id| encode_id(*id)).collect();
+        assert!(encoded.windows(2).all(|pair| pair[0] < pair[1]));
+        for (id, bin) in ids.iter().zip(encoded) {
+            assert_eq!(*id, decode_id(&bin).unwrap());
+        }
+        assert!(decode_id(&[0; 15]).is_err());
+    }
 }
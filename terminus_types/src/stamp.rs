@@ -0,0 +1,94 @@
+//! proof of work on posts, bound to where they go and what they say.
+//!
+//! a stamp is a nonce, its work is the hash of challenge and nonce.
+//! difficulty is the leading zero bits the work should have.
+//! the server mixes in a salt it changes now and then, so old stamps grow too old to take.
+
+use serde::{Deserialize, Serialize};
+
+/// given by the server, stamps made with a salt it dropped are refused.
+pub type Salt = [u8; 16];
+
+/// what a stamp should be made with, as the server tells it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Difficulty {
+    /// zero if no stamp is needed.
+    pub bits: u32,
+    pub salt: Salt,
+}
+
+/// tries to expect before a stamp is found.
+pub fn expected_tries(difficulty: u32) -> u64 {
+    1u64.checked_shl(difficulty).unwrap_or(u64::MAX)
+}
+
+/// what a stamp is made for, salt, parent and hash of title and content.
+pub fn challenge(salt: &Salt, parent: &[u8], title: &str, content: &str) -> [u8; 32] {
+    let mut body = blake3::Hasher::new();
+    body.update(&(title.len() as u64).to_le_bytes());
+    body.update(title.as_bytes());
+    body.update(content.as_bytes());
+    let mut hasher = blake3::Hasher::new_derive_key("terminus post stamp v1");
+    hasher.update(salt);
+    hasher.update(&(parent.len() as u64).to_le_bytes());
+    hasher.update(parent);
+    hasher.update(body.finalize().as_bytes());
+    *hasher.finalize().as_bytes()
+}
+
+pub fn work(challenge: &[u8; 32], nonce: u64) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(challenge);
+    hasher.update(&nonce.to_le_bytes());
+    *hasher.finalize().as_bytes()
+}
+
+/// leading zero bits of work.
+pub fn zeros(work: &[u8; 32]) -> u32 {
+    let mut zeros = 0;
+    for byte in work.iter() {
+        zeros += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    zeros
+}
+
+/// try nonces from start on, none if no one in tries is good enough.
+pub fn solve(challenge: &[u8; 32], difficulty: u32, start: u64, tries: u64) -> Option<u64> {
+    (0..tries)
+        .map(|i| start.wrapping_add(i))
+        .find(|nonce| zeros(&work(challenge, *nonce)) >= difficulty)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{challenge, solve, work, zeros};
+
+    #[test]
+    fn zero_bits() {
+        let mut hash = [0xff; 32];
+        assert_eq!(0, zeros(&hash));
+        hash[0] = 0;
+        hash[1] = 0x0f;
+        assert_eq!(12, zeros(&hash));
+        assert_eq!(256, zeros(&[0; 32]));
+    }
+
+    #[test]
+    fn bound_stamp() {
+        let salt = [7; 16];
+        let here = challenge(&salt, &[1; 16], "t", "c");
+        let nonce = solve(&here, 8, 0, u64::MAX).unwrap();
+        assert!(zeros(&work(&here, nonce)) >= 8);
+        // same start, same stamp
+        assert_eq!(Some(nonce), solve(&here, 8, 0, nonce + 1));
+        assert_ne!(here, challenge(&salt, &[2; 16], "t", "c"));
+        assert_ne!(here, challenge(&salt, &[1; 16], "t", "d"));
+        // a new salt, the stamp is no good
+        assert_ne!(here, challenge(&[8; 16], &[1; 16], "t", "c"));
+        // title and content can not trade bytes
+        assert_ne!(challenge(&salt, &[], "ab", "c"), challenge(&salt, &[], "a", "bc"));
+    }
+}